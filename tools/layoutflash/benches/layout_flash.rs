@@ -0,0 +1,34 @@
+// Lays out a representative image in memory: a handful of small areas
+// and several multi-megabyte ones, filled, patterned and from files.
+// Run with `cargo bench`.
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use layoutflash_tool::{pack, Area, LayoutOpts};
+use std::{env, fs, process};
+
+fn areas(file: &str) -> Vec<Area> {
+    let mut areas: Vec<Area> = (0..16)
+        .map(|i| Area {
+            name: format!("area@{}", i),
+            size: if i % 4 == 0 { 0x1000 } else { 2 << 20 },
+            ..Default::default()
+        })
+        .collect();
+    areas[1].fill = Some(0);
+    areas[2].fill_pattern = Some(vec![0xde, 0xad, 0xbe, 0xef]);
+    areas[3].file = Some(file.to_string());
+    areas
+}
+
+fn bench_pack(c: &mut Criterion) {
+    let file = env::temp_dir().join(format!("layoutflash-bench-{}", process::id()));
+    fs::write(&file, vec![0x5a; 1 << 20]).unwrap();
+    let file = file.to_str().unwrap().to_string();
+    let opts = LayoutOpts::default();
+    c.bench_function("pack 16 areas", |b| {
+        b.iter(|| pack(black_box(&mut areas(&file)), &opts).unwrap())
+    });
+    fs::remove_file(file).unwrap();
+}
+
+criterion_group!(benches, bench_pack);
+criterion_main!(benches);
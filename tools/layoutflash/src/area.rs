@@ -0,0 +1,1116 @@
+use crate::cbfs::{cbfs_type, type_names};
+use crate::error::{LayoutError, Result};
+use crate::gpt::parse_guid;
+use crate::layout::LayoutOpts;
+use crate::schema::{COMPRESSIONS, LENGTH_PREFIXES, STATUSES};
+use crate::trailer::{check_trailer, TRAILER_SIZE};
+use fdt::node::NodeProperty;
+use log::{debug, info};
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+// AreaOpts: the options that control how Areas are read from the fdt.
+#[derive(clap::Args, Debug, Default)]
+pub struct AreaOpts {
+    /// Reject areas that have neither a `description` nor a `compatible`,
+    /// and areas that don't start on a --page-size page
+    #[clap(long, global = true)]
+    pub strict: bool,
+    /// The most areas a layout may have [default: 4096]
+    #[clap(long, global = true)]
+    pub max_areas: Option<usize>,
+    /// Merge the areas of another fdt over those of the first, matching
+    /// them by description. May be repeated; later layers win.
+    #[clap(long = "layer", global = true)]
+    pub layers: Vec<PathBuf>,
+    /// Require each input fdt to be followed by a CRC trailer over its
+    /// totalsize bytes, and refuse it if the CRC does not match
+    #[clap(long, global = true)]
+    pub fdt_crc: bool,
+}
+
+impl AreaOpts {
+    // check_area_count: refuse runaway inputs before doing any work on them.
+    fn check_area_count(&self, count: usize) -> Result<()> {
+        let max = self.max_areas.unwrap_or(4096);
+        if count > max {
+            return Err(LayoutError::Area(format!(
+                "More than {} areas; use --max-areas to allow more",
+                max
+            )));
+        }
+        Ok(())
+    }
+}
+
+// LengthPrefix: how a `length-prefix` Area stores the length of its
+// contents in front of them.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LengthPrefix {
+    U32Le,
+    U32Be,
+}
+
+impl LengthPrefix {
+    pub const SIZE: usize = 4;
+
+    // The prefix for contents of len bytes, or None if it can't hold len.
+    pub fn encode(self, len: usize) -> Option<[u8; LengthPrefix::SIZE]> {
+        let len = u32::try_from(len).ok()?;
+        Some(match self {
+            LengthPrefix::U32Le => len.to_le_bytes(),
+            LengthPrefix::U32Be => len.to_be_bytes(),
+        })
+    }
+}
+
+// NOTE: we don't use u32. At the rate that SPI flash is expanding, we're going to see
+// 5B addressing soon I bet. The size limitation should be a function of the destination,
+// not this program. This problem should just stupidly arrange things.
+#[derive(Clone, Debug, Default)]
+pub struct Area {
+    pub name: String,
+    pub offset: Option<usize>,
+    pub size: usize,
+    pub file: Option<String>,
+    pub description: Option<String>,
+    pub index: Option<usize>,
+    pub compatible: Option<String>,
+    pub align: Option<usize>,
+    pub end: Option<usize>,
+    pub pad_file: Option<String>,
+    pub crc_trailer: bool,
+    pub compression: Option<String>,
+    pub read_only: bool,
+    pub preserve: bool,
+    pub static_: bool,
+    pub anchor: Option<String>,
+    pub fill: Option<u8>,
+    pub truncate_input: bool,
+    pub type_: Option<String>,
+    pub signature_size: Option<usize>,
+    pub partition: bool,
+    pub type_guid: Option<String>,
+    pub fill_pattern: Option<Vec<u8>>,
+    pub covers: Vec<String>,
+    pub disabled: bool,
+    pub length_prefix: Option<LengthPrefix>,
+}
+
+impl Area {
+    // The human readable name of the area, falling back to the node name.
+    pub fn label(&self) -> &str {
+        self.description.as_deref().unwrap_or(&self.name)
+    }
+
+    // The contents of the Area before any data is written over them: its
+    // fill-pattern repeated from the start of the Area, the final
+    // repetition cut short, or else its fill byte, 0xff by default.
+    pub fn filled(&self) -> Vec<u8> {
+        match &self.fill_pattern {
+            Some(pattern) => pattern.iter().copied().cycle().take(self.size).collect(),
+            None => vec![self.fill.unwrap_or(0xff); self.size],
+        }
+    }
+
+    // The alignment of the Area's offset, if any.
+    pub fn alignment(&self, opts: &LayoutOpts) -> Option<usize> {
+        self.align.or(opts.align_all.map(|a| a as usize))
+    }
+
+    // The bytes reserved in the Area, and what for. A length prefix is at
+    // the start, before the contents. The CRC trailer is last, and a
+    // `signature-size` reservation, left filled for a signing step, is
+    // before it.
+    pub fn reservations(&self) -> Vec<(&'static str, usize)> {
+        let mut r = vec![];
+        if self.length_prefix.is_some() {
+            r.push(("length prefix", LengthPrefix::SIZE));
+        }
+        if let Some(size) = self.signature_size {
+            r.push(("signature", size));
+        }
+        if self.crc_trailer {
+            r.push(("crc trailer", TRAILER_SIZE));
+        }
+        r
+    }
+
+    // The bytes available for the Area's contents, less any reservations.
+    pub fn capacity(&self) -> usize {
+        let reserved: usize = self.reservations().iter().map(|(_, size)| size).sum();
+        self.size.saturating_sub(reserved)
+    }
+
+    // The names of the Area's boolean properties that are set.
+    pub fn flags(&self) -> Vec<&'static str> {
+        [
+            (self.crc_trailer, "crc-trailer"),
+            (self.partition, "partition"),
+            (self.preserve, "preserve"),
+            (self.read_only, "read-only"),
+            (self.static_, "static"),
+            (self.truncate_input, "truncate-input"),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect()
+    }
+
+    // A file of "-" or "$(STDIN)" means the contents come from standard input.
+    pub fn reads_stdin(&self) -> bool {
+        matches!(self.file.as_deref(), Some("-") | Some("$(STDIN)"))
+    }
+}
+
+// expand_path: expand the environment variables in an Area's file path.
+// Returns None if the path is an unused environment variable.
+pub(crate) fn expand_path(path: &str) -> Option<String> {
+    let mut path = path.to_string();
+    // Allow environment variables in the path.
+    for (key, value) in env::vars() {
+        path = str::replace(&path, &format!("$({})", key), &value);
+    }
+
+    if path.starts_with("$(") && path.ends_with(')') {
+        return None;
+    }
+    Some(path)
+}
+
+pub(crate) fn read_file(path: &str) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| LayoutError::Open(path.to_string(), e))
+}
+
+// set_property: record an Area property from the fdt. A property whose
+// value doesn't have the expected type, or isn't one of the values it
+// may take, is an error naming the node. check_schema checks properties
+// with this too.
+pub(crate) fn set_property(a: &mut Area, p: &NodeProperty) -> Result<()> {
+    debug!(" {:?} {:?}, {:?}", p.name, p.as_str(), p.as_usize());
+
+    // There can be all kinds of properties in a node. Those that aren't
+    // Area properties are left alone, for other users of the fdt.
+
+    match p.name {
+        "anchor" => {
+            a.anchor = Some(string(a, p)?.to_string());
+        }
+        "align" => {
+            let align = number(a, p)?;
+            if align == 0 {
+                return Err(bad_value(a, p, "more than 0", "0".to_string()));
+            }
+            a.align = Some(align);
+        }
+        "compatible" => {
+            a.compatible = Some(string(a, p)?.to_string());
+        }
+        "end" => {
+            a.end = Some(number(a, p)?);
+        }
+        "covers" => {
+            a.covers = strings(a, p)?;
+        }
+        "compression" => {
+            let c = string(a, p)?;
+            if !COMPRESSIONS.contains(&c) {
+                return Err(bad_value(
+                    a,
+                    p,
+                    &format!("one of {}", COMPRESSIONS.join(", ")),
+                    format!("'{}'", c),
+                ));
+            }
+            a.compression = Some(c.to_string());
+        }
+        "crc-trailer" => {
+            a.crc_trailer = flag(a, p)?;
+        }
+        "description" => {
+            a.description = Some(string(a, p)?.to_string());
+        }
+        "file" => {
+            a.file = Some(string(a, p)?.to_string());
+        }
+        "fill" => {
+            let fill = number(a, p)?;
+            a.fill = Some(
+                u8::try_from(fill)
+                    .map_err(|_| bad_value(a, p, "a byte", format!("{:#x}", fill)))?,
+            );
+        }
+        "fill-pattern" => {
+            if p.value.is_empty() {
+                return Err(bad_value(
+                    a,
+                    p,
+                    "a non-empty byte string",
+                    "empty".to_string(),
+                ));
+            }
+            a.fill_pattern = Some(p.value.to_vec());
+        }
+        "index" => {
+            a.index = Some(number(a, p)?);
+        }
+        "partition" => {
+            a.partition = flag(a, p)?;
+        }
+        "preserve" => {
+            a.preserve = flag(a, p)?;
+        }
+        "read-only" => {
+            a.read_only = flag(a, p)?;
+        }
+        "static" => {
+            a.static_ = flag(a, p)?;
+        }
+        "status" => {
+            a.disabled = match string(a, p)? {
+                "okay" | "ok" => false,
+                "disabled" => true,
+                s => {
+                    return Err(bad_value(
+                        a,
+                        p,
+                        &format!("one of {}", STATUSES.join(", ")),
+                        format!("'{}'", s),
+                    ))
+                }
+            };
+        }
+        "type-guid" => {
+            let g = string(a, p)?;
+            if parse_guid(g).is_none() {
+                return Err(bad_value(a, p, "a GUID", format!("'{}'", g)));
+            }
+            a.type_guid = Some(g.to_string());
+        }
+        "type" => {
+            let t = string(a, p)?;
+            if cbfs_type(t).is_none() {
+                return Err(bad_value(
+                    a,
+                    p,
+                    &format!("one of {}", type_names()),
+                    format!("'{}'", t),
+                ));
+            }
+            a.type_ = Some(t.to_string());
+        }
+        "truncate-input" => {
+            a.truncate_input = flag(a, p)?;
+        }
+        "pad-file" => {
+            a.pad_file = Some(string(a, p)?.to_string());
+        }
+        "length-prefix" => {
+            a.length_prefix = match string(a, p)? {
+                "none" => None,
+                "u32-le" => Some(LengthPrefix::U32Le),
+                "u32-be" => Some(LengthPrefix::U32Be),
+                s => {
+                    return Err(bad_value(
+                        a,
+                        p,
+                        &format!("one of {}", LENGTH_PREFIXES.join(", ")),
+                        format!("'{}'", s),
+                    ))
+                }
+            };
+        }
+        "offset" => {
+            a.offset = Some(number(a, p)?);
+        }
+        "size" => {
+            a.size = number(a, p)?;
+        }
+        "signature-size" => {
+            a.signature_size = Some(number(a, p)?);
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn bad_property(a: &Area, p: &NodeProperty, want: &str) -> LayoutError {
+    LayoutError::Area(format!(
+        "Area '{}': property '{}' should be {}, but is {} bytes long",
+        a.name,
+        p.name,
+        want,
+        p.value.len()
+    ))
+}
+
+fn bad_value(a: &Area, p: &NodeProperty, want: &str, value: String) -> LayoutError {
+    LayoutError::Area(format!(
+        "Area '{}': property '{}' should be {}, but is {}",
+        a.name, p.name, want, value
+    ))
+}
+
+fn number(a: &Area, p: &NodeProperty) -> Result<usize> {
+    p.as_usize()
+        .ok_or_else(|| bad_property(a, p, "a 32 or 64 bit number"))
+}
+
+// string: a NUL terminated UTF-8 string property.
+fn string<'a>(a: &Area, p: &NodeProperty<'a>) -> Result<&'a str> {
+    p.value
+        .strip_suffix(&[0])
+        .and_then(|s| std::str::from_utf8(s).ok())
+        .ok_or_else(|| bad_property(a, p, "a string"))
+}
+
+// flag: an empty property, which is true by being there.
+fn flag(a: &Area, p: &NodeProperty) -> Result<bool> {
+    match p.value.len() {
+        0 => Ok(true),
+        _ => Err(bad_property(a, p, "empty")),
+    }
+}
+
+// strings: the strings of a string list property, e.g. "a", "b".
+fn strings(a: &Area, p: &NodeProperty) -> Result<Vec<String>> {
+    string(a, p)?;
+    p.value
+        .strip_suffix(&[0])
+        .unwrap_or(p.value)
+        .split(|b| *b == 0)
+        .map(|s| {
+            std::str::from_utf8(s)
+                .map(|s| s.to_string())
+                .map_err(|_| bad_property(a, p, "a string list"))
+        })
+        .collect()
+}
+
+// resolve_end: derive an Area's size from its `end` property, the
+// exclusive end offset, if it has one. `end` needs an explicit offset,
+// and if `size` is given too, the two must agree.
+fn resolve_end(a: &mut Area, has_size: bool) -> Result<()> {
+    let end = match a.end {
+        Some(end) => end,
+        None => return Ok(()),
+    };
+    let err = |msg: String| Err(LayoutError::Area(msg));
+    let offset = match a.offset {
+        Some(offset) => offset,
+        None => return err(format!("Area '{}' has an end but no offset", a.name)),
+    };
+    if end <= offset {
+        return err(format!(
+            "Area '{}' ends at {:#x}, which is not after its offset {:#x}",
+            a.name, end, offset
+        ));
+    }
+    if has_size && a.size != end - offset {
+        return err(format!(
+            "Area '{}' has size {:#x}, but its offset {:#x} and end {:#x} give {:#x}",
+            a.name,
+            a.size,
+            offset,
+            end,
+            end - offset
+        ));
+    }
+    a.size = end - offset;
+    Ok(())
+}
+
+// default_description: the description of an Area that has none, the
+// node name less its "area@" prefix, e.g. "10000" for area@10000.
+fn default_description(name: &str) -> String {
+    name.strip_prefix("area@").unwrap_or(name).to_string()
+}
+
+// An Area without a `description` or `compatible` can't be named in
+// exported metadata. That is an error with --strict. Otherwise, as for
+// any Area without a description, one is derived from the node name.
+pub fn create_areas(fdt: &fdt::Fdt, opts: &AreaOpts) -> Result<Vec<Area>> {
+    // Assemble the bits of the fdt we care about into Areas.
+    let mut areas: Vec<Area> = vec![];
+
+    for node in fdt.find_all_nodes("/flash-info/areas") {
+        debug!("{:?}", node.name);
+        add_areas(node, &[], opts, &mut areas)?;
+    }
+
+    Ok(areas)
+}
+
+// The properties an Area inherits from the groups it is in.
+const INHERITED: &[&str] = &[
+    "align",
+    "compression",
+    "fill",
+    "fill-pattern",
+    "preserve",
+    "read-only",
+    "static",
+];
+
+// is_group: whether a node under the areas node is a group of Areas.
+pub(crate) fn is_group(name: &str) -> bool {
+    name == "group" || name.starts_with("group@")
+}
+
+// without_fill: the inherited properties, less `fill` and `fill-pattern`
+// if the node sets either itself.
+fn without_fill<'a>(
+    inherited: &[NodeProperty<'a>],
+    node: fdt::node::FdtNode<'_, 'a>,
+) -> Vec<NodeProperty<'a>> {
+    let fills = ["fill", "fill-pattern"];
+    let own = fills.iter().any(|f| node.property(f).is_some());
+    inherited
+        .iter()
+        .filter(|p| !(own && fills.contains(&p.name)))
+        .cloned()
+        .collect()
+}
+
+// add_areas: add the Areas under a node, descending into groups. A child
+// node named group, or group@..., holds Areas that share its INHERITED
+// properties, e.g.
+//     group@a {
+//         align = <0x1000>;
+//         read-only;
+//         area@0 { ... };
+//         area@1 { ... };
+//     };
+// An Area's own properties override those of its groups, and an inner
+// group's those of an outer one. `fill` and `fill-pattern` count as one
+// property here: setting either drops both of those inherited.
+fn add_areas<'a>(
+    node: fdt::node::FdtNode<'_, 'a>,
+    inherited: &[NodeProperty<'a>],
+    opts: &AreaOpts,
+    areas: &mut Vec<Area>,
+) -> Result<()> {
+    for child in node.children() {
+        debug!("    {}", child.name);
+        if is_group(child.name) {
+            let mut defaults = without_fill(inherited, child);
+            defaults.extend(child.properties().filter(|p| INHERITED.contains(&p.name)));
+            add_areas(child, &defaults, opts, areas)?;
+            continue;
+        }
+        opts.check_area_count(areas.len() + 1)?;
+        let mut a: Area = Area {
+            name: child.name.to_string(),
+            ..Default::default()
+        };
+        for p in without_fill(inherited, child) {
+            set_property(&mut a, &p)?;
+        }
+        for p in child.properties() {
+            set_property(&mut a, &p)?;
+        }
+        settle(&mut a, child.property("size").is_some(), opts)?;
+        areas.push(a);
+    }
+    Ok(())
+}
+
+// settle: derive what an Area's properties imply, once they are all set:
+// its size from an `end`, where has_size says whether a `size` was set
+// alongside it, and a description from its node name if it has none.
+fn settle(a: &mut Area, has_size: bool, opts: &AreaOpts) -> Result<()> {
+    resolve_end(a, has_size)?;
+    if a.description.as_deref() == Some("") {
+        a.description = None;
+    }
+    if a.description.is_none() {
+        if a.compatible.is_none() && opts.strict {
+            return Err(LayoutError::Area(format!(
+                "Area '{}' needs a description or a compatible in strict mode",
+                a.name
+            )));
+        }
+        a.description = Some(default_description(&a.name));
+    }
+    Ok(())
+}
+
+// apply_overlays: apply the fdt overlays held in "oreboot,dt-overlay"
+// Areas to the other Areas, before they are laid out. Only fragments
+// with a target-path naming an Area node are supported, e.g.
+//     fragment@0 {
+//         target-path = "/flash-info/areas/area@1";
+//         __overlay__ {
+//             size = <0x100000>;
+//         };
+//     };
+// whose __overlay__ properties add to or override the Area's properties,
+// and are settled again, so that e.g. an overlaid `end` sets the size.
+// The overlay Area itself is laid out like any other.
+pub fn apply_overlays(areas: &mut [Area], opts: &AreaOpts) -> Result<()> {
+    let overlays: Vec<String> = areas
+        .iter()
+        .filter(|a| a.compatible.as_deref() == Some("oreboot,dt-overlay"))
+        .filter_map(|a| a.file.as_deref().and_then(expand_path))
+        .collect();
+    for path in overlays {
+        let data = read_file(&path)?;
+        let overlay = fdt::Fdt::new(&data)
+            .map_err(|e| LayoutError::Fdt(format!("Overlay {}: {}", path, e)))?;
+        for fragment in overlay.find_all_nodes("/fragment") {
+            let target = match fragment.property("target-path").and_then(|p| p.as_str()) {
+                Some(target) => target,
+                None => {
+                    return Err(LayoutError::Area(format!(
+                        "Overlay {}: {} has no target-path, only target-path is supported",
+                        path, fragment.name
+                    )))
+                }
+            };
+            let a = target
+                .strip_prefix("/flash-info/areas/")
+                .and_then(|name| areas.iter_mut().find(|a| a.name == name))
+                .ok_or_else(|| {
+                    LayoutError::Area(format!("Overlay {}: no area matches {}", path, target))
+                })?;
+            info!("Overlay {} applies to {}", path, a.name);
+            for node in fragment.children().filter(|n| n.name == "__overlay__") {
+                for p in node.properties() {
+                    set_property(a, &p)?;
+                }
+                settle(a, node.property("size").is_some(), opts)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// read_areas: read the Areas of a firmware device tree file, merge those
+// of each of the --layer fdts over them, as for imports, apply any
+// overlays among them, and drop the Areas with `status = "disabled"`.
+pub fn read_areas(path: &Path, opts: &AreaOpts) -> Result<Vec<Area>> {
+    let mut areas = read_imported(path, opts, &mut vec![])?;
+    for layer in &opts.layers {
+        merge(&mut areas, read_imported(layer, opts, &mut vec![])?);
+    }
+    opts.check_area_count(areas.len())?;
+    apply_overlays(&mut areas, opts)?;
+    // Disabled Areas are dropped only now, so that a layer or overlay can
+    // enable them. They are left out of the layout and the image entirely.
+    areas.retain(|a| {
+        if a.disabled {
+            debug!("{} is disabled, leaving it out", a.label());
+        }
+        !a.disabled
+    });
+    Ok(areas)
+}
+
+// merge: add Areas to a list, each replacing the Area with its label if
+// there is one.
+fn merge(areas: &mut Vec<Area>, layer: Vec<Area>) {
+    for a in layer {
+        match areas.iter_mut().find(|b| b.label() == a.label()) {
+            Some(b) => *b = a,
+            None => areas.push(a),
+        }
+    }
+}
+
+// read_fdt: the bytes of an fdt file. With --fdt-crc the fdt must be
+// followed by a trailer, as written for a `crc-trailer` Area, whose CRC
+// covers the fdt's totalsize bytes; it is checked before the fdt is
+// walked, so a corrupted file is refused rather than misread.
+pub(crate) fn read_fdt(path: &Path, opts: &AreaOpts) -> Result<Vec<u8>> {
+    let data = fs::read(path)?;
+    if opts.fdt_crc {
+        let total = data
+            .get(4..8)
+            .map(|t| u32::from_be_bytes(t.try_into().unwrap()) as usize)
+            .unwrap_or(0);
+        let checked = data
+            .get(..total + TRAILER_SIZE)
+            .and_then(check_trailer)
+            .map_or(false, |payload| payload.len() == total);
+        if !checked {
+            return Err(LayoutError::Fdt(format!(
+                "{}: the CRC trailer after the fdt is missing or does not match",
+                path.display()
+            )));
+        }
+    }
+    Ok(data)
+}
+
+// read_imported: read the Areas of an fdt and of the fdts it imports.
+// The areas node may name other fdts in an `import` string list, with
+// paths relative to the importing file, e.g.
+//     areas {
+//         import = "common.dtb", "payloads.dtb";
+//         ...
+//     };
+// Imported Areas come first, in import order. A local Area with the same
+// label as an imported one replaces it in place.
+// `stack` holds the files being read, to catch import cycles.
+fn read_imported(path: &Path, opts: &AreaOpts, stack: &mut Vec<PathBuf>) -> Result<Vec<Area>> {
+    // However an import spells the path, e.g. with "..", the same file is
+    // the same path on the stack.
+    let path = &fs::canonicalize(path)?;
+    if stack.iter().any(|p| p == path) {
+        return Err(LayoutError::Area(format!(
+            "{} imports itself",
+            path.display()
+        )));
+    }
+    info!("Read in {:?}", path);
+    let data = read_fdt(path, opts)?;
+    let fdt = fdt::Fdt::new(&data)
+        .map_err(|e| LayoutError::Fdt(format!("fdt from data failed: {}", e)))?;
+    let local = create_areas(&fdt, opts)?;
+
+    let imports = fdt
+        .find_node("/flash-info/areas")
+        .and_then(|n| n.property("import"))
+        .and_then(|p| p.as_str())
+        .unwrap_or("");
+    let mut areas = Vec::new();
+    stack.push(path.to_path_buf());
+    for import in imports.split('\0').filter(|i| !i.is_empty()) {
+        let import = path.parent().unwrap_or(Path::new("")).join(import);
+        areas.extend(read_imported(&import, opts, stack)?);
+    }
+    stack.pop();
+    merge(&mut areas, local);
+    opts.check_area_count(areas.len())?;
+    Ok(areas)
+}
+
+// flash_reg: the base address and size of the flash, from the `reg`
+// property of /flash-info, if it has one. As /flash-info is a child of
+// the root, the root's #address-cells and #size-cells apply, defaulting
+// to 2 and 1 as in the devicetree spec.
+pub fn flash_reg(fdt: &fdt::Fdt) -> Result<Option<(u64, u64)>> {
+    let reg = match fdt.find_node("/flash-info").and_then(|n| n.property("reg")) {
+        Some(reg) => reg,
+        None => return Ok(None),
+    };
+    let root_cells = |name: &str, default: usize| -> Result<usize> {
+        match fdt.root().property(name) {
+            Some(p) => p
+                .as_usize()
+                .ok_or_else(|| LayoutError::Area(format!("The root's {} is not a number", name))),
+            None => Ok(default),
+        }
+    };
+    let address_cells = root_cells("#address-cells", 2)?;
+    let size_cells = root_cells("#size-cells", 1)?;
+    if address_cells > 2 || size_cells > 2 || reg.value.len() < 4 * (address_cells + size_cells) {
+        return Err(LayoutError::Area(format!(
+            "flash-info: reg should hold a {} cell address and a {} cell size, but is {} bytes long",
+            address_cells,
+            size_cells,
+            reg.value.len()
+        )));
+    }
+    let (address, size) = reg.value.split_at(4 * address_cells);
+    let number = |cells: &[u8]| {
+        cells.chunks(4).fold(0u64, |n, c| {
+            n << 32 | u32::from_be_bytes(c.try_into().unwrap()) as u64
+        })
+    };
+    Ok(Some((number(address), number(&size[..4 * size_cells]))))
+}
+
+// Metadata: the root properties that describe the layout as a whole,
+//     model = "...";           the board the layout is for
+//     layout-version = <n>;    the revision of the layout
+#[derive(Debug, Default, PartialEq)]
+pub struct Metadata {
+    pub model: Option<String>,
+    pub layout_version: Option<u32>,
+}
+
+// metadata: the Metadata in the root of an fdt.
+pub fn metadata(fdt: &fdt::Fdt) -> Result<Metadata> {
+    let root = fdt.root();
+    let layout_version = match root.property("layout-version") {
+        Some(p) if p.value.len() == 4 => Some(u32::from_be_bytes(p.value.try_into().unwrap())),
+        Some(p) => {
+            return Err(LayoutError::Area(format!(
+                "The root's layout-version should be a 32 bit number, but is {} bytes long",
+                p.value.len()
+            )))
+        }
+        None => None,
+    };
+    Ok(Metadata {
+        model: root
+            .property("model")
+            .and_then(|p| p.as_str())
+            .map(|s| s.to_string()),
+        layout_version,
+    })
+}
+
+// read_metadata: metadata for the fdt at a path.
+pub fn read_metadata(path: &Path, opts: &AreaOpts) -> Result<Metadata> {
+    let data = read_fdt(path, opts)?;
+    let fdt = fdt::Fdt::new(&data)
+        .map_err(|e| LayoutError::Fdt(format!("fdt from data failed: {}", e)))?;
+    metadata(&fdt)
+}
+
+// FlashInfo: what /flash-info says about the flash part itself.
+#[derive(Debug, Default, PartialEq)]
+pub struct FlashInfo {
+    // The base address and size, from `reg`; see flash_reg.
+    pub reg: Option<(u64, u64)>,
+    // The size of a program page, from `page-size`.
+    pub page_size: Option<u64>,
+}
+
+// flash_info: the FlashInfo of an fdt.
+pub fn flash_info(fdt: &fdt::Fdt) -> Result<FlashInfo> {
+    let page_size = match fdt
+        .find_node("/flash-info")
+        .and_then(|n| n.property("page-size"))
+    {
+        Some(p) => Some(p.as_usize().ok_or_else(|| {
+            LayoutError::Area(format!(
+                "flash-info: page-size should be a 32 or 64 bit number, but is {} bytes long",
+                p.value.len()
+            ))
+        })? as u64),
+        None => None,
+    };
+    Ok(FlashInfo {
+        reg: flash_reg(fdt)?,
+        page_size,
+    })
+}
+
+// read_flash_info: flash_info for the fdt at a path.
+pub fn read_flash_info(path: &Path, opts: &AreaOpts) -> Result<FlashInfo> {
+    let data = read_fdt(path, opts)?;
+    let fdt = fdt::Fdt::new(&data)
+        .map_err(|e| LayoutError::Fdt(format!("fdt from data failed: {}", e)))?;
+    flash_info(&fdt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{compute_layout, pack};
+    use crate::testutil::{area, temp_path};
+
+    #[test]
+    fn overlay_changes_area_size() {
+        static DATA: &[u8] = include_bytes!("testdata/test.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let mut areas = create_areas(&fdt, &AreaOpts::default()).unwrap();
+        assert_eq!(areas[1].size, 0x80000);
+        let mut overlay = area("overlay", None, 0);
+        overlay.compatible = Some("oreboot,dt-overlay".to_string());
+        overlay.file = Some("src/testdata/overlay.dtb".to_string());
+        areas.push(overlay);
+        apply_overlays(&mut areas, &AreaOpts::default()).unwrap();
+        assert_eq!(areas[1].size, 0x40000);
+        assert_eq!(areas[1].description.as_deref(), Some("Small DTFS"));
+        assert_eq!(areas[2].size, 0x80000);
+
+        // An overlaid end changes the size, and an emptied description
+        // falls back to the node name.
+        areas.last_mut().unwrap().file = Some("src/testdata/overlay-end.dtb".to_string());
+        apply_overlays(&mut areas, &AreaOpts::default()).unwrap();
+        assert_eq!(areas[2].size, 0x20000);
+        assert_eq!(areas[2].description.as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn import_and_override() {
+        let areas = read_areas(Path::new("src/testdata/import.dtb"), &AreaOpts::default()).unwrap();
+        let got: Vec<_> = areas.iter().map(|a| (a.label(), a.size)).collect();
+        assert_eq!(
+            got,
+            [("Boot Blob", 0x100), ("Payload", 0x400), ("Extra", 0x10)]
+        );
+    }
+
+    #[test]
+    fn description_fallback_in_errors() {
+        static DATA: &[u8] = include_bytes!("testdata/unnamed.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let mut areas = create_areas(&fdt, &AreaOpts::default()).unwrap();
+        areas[0].offset = Some(0);
+        areas[1].offset = Some(0x80);
+        let err = compute_layout(&mut areas, &LayoutOpts::default()).unwrap_err();
+        assert!(
+            err.to_string().contains("next area '100' starts at 128"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn too_many_areas() {
+        static DATA: &[u8] = include_bytes!("testdata/unnamed.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let opts = AreaOpts {
+            max_areas: Some(1),
+            ..Default::default()
+        };
+        let err = create_areas(&fdt, &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "More than 1 areas; use --max-areas to allow more"
+        );
+        opts.check_area_count(1).unwrap();
+    }
+
+    #[test]
+    fn import_cycle() {
+        let err =
+            read_areas(Path::new("src/testdata/cycle-a.dtb"), &AreaOpts::default()).unwrap_err();
+        assert!(
+            err.to_string().ends_with("cycle-a.dtb imports itself"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn layers() {
+        let opts = AreaOpts {
+            layers: vec![PathBuf::from("src/testdata/layer.dtb")],
+            ..Default::default()
+        };
+        let areas = read_areas(Path::new("src/testdata/import-common.dtb"), &opts).unwrap();
+        let got: Vec<_> = areas.iter().map(|a| (a.label(), a.size)).collect();
+        assert_eq!(
+            got,
+            [("Boot Blob", 0x800), ("Payload", 0x200), ("Board", 0x40)]
+        );
+    }
+
+    #[test]
+    fn flash_reg_gives_base_and_size() {
+        let info = read_flash_info(
+            Path::new("src/testdata/flash-reg.dtb"),
+            &AreaOpts::default(),
+        )
+        .unwrap();
+        assert_eq!(info.reg, Some((0xff000000, 0x1000000)));
+        assert_eq!(info.page_size, Some(0x100));
+        let opts = LayoutOpts::default().with_flash_info(&info);
+        assert_eq!(opts.base_address, Some(0xff000000));
+        assert_eq!(opts.flash_size, Some(0x1000000));
+        assert_eq!(opts.page_size, Some(0x100));
+
+        // The command line wins.
+        let opts = LayoutOpts {
+            flash_size: Some(0x800000),
+            ..Default::default()
+        }
+        .with_flash_info(&info);
+        assert_eq!(opts.base_address, Some(0xff000000));
+        assert_eq!(opts.flash_size, Some(0x800000));
+
+        static DATA: &[u8] = include_bytes!("testdata/unnamed.dtb");
+        let info = flash_info(&fdt::Fdt::new(DATA).unwrap()).unwrap();
+        assert_eq!(info, FlashInfo::default());
+    }
+
+    #[test]
+    fn fdt_crc() {
+        let mut data = fs::read("src/testdata/flash-reg.dtb").unwrap();
+        let t = crate::trailer::trailer(&data);
+        data.extend_from_slice(&t);
+        let path = temp_path("fdt-crc.dtb");
+        fs::write(&path, &data).unwrap();
+        let checked = AreaOpts {
+            fdt_crc: true,
+            ..Default::default()
+        };
+        assert_eq!(read_areas(&path, &checked).unwrap().len(), 1);
+        // Without --fdt-crc the trailer is ignored.
+        assert_eq!(read_areas(&path, &AreaOpts::default()).unwrap().len(), 1);
+
+        // A flipped bit in the fdt is caught.
+        data[0x40] ^= 1;
+        fs::write(&path, &data).unwrap();
+        assert!(matches!(
+            read_areas(&path, &checked),
+            Err(LayoutError::Fdt(_))
+        ));
+
+        // As is a missing trailer.
+        let err = read_areas(Path::new("src/testdata/flash-reg.dtb"), &checked).unwrap_err();
+        assert!(err.to_string().contains("CRC trailer"), "{}", err);
+    }
+
+    #[test]
+    fn groups() {
+        static DATA: &[u8] = include_bytes!("testdata/group.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let mut areas = create_areas(&fdt, &AreaOpts::default()).unwrap();
+        let got: Vec<_> = areas.iter().map(|a| (a.label(), a.align, a.fill)).collect();
+        assert_eq!(
+            got,
+            [
+                ("Header", None, None),
+                ("A-Boot", Some(0x1000), Some(0)),
+                ("A-Payload", Some(0x1000), Some(0xaa)),
+                ("B-Pattern", None, None),
+                ("B-Fill", None, Some(0)),
+            ]
+        );
+        // A fill of the Area's own overrides its group's fill-pattern.
+        assert_eq!(areas[3].fill_pattern.as_deref(), Some(&[1, 2][..]));
+        assert_eq!(areas[4].fill_pattern, None);
+
+        let image = pack(&mut areas, &LayoutOpts::default()).unwrap();
+        assert_eq!(image.len(), 0x2120);
+        assert!(image[..0x80].iter().all(|&b| b == 0xff));
+        assert!(image[0x1000..0x1100].iter().all(|&b| b == 0));
+        assert!(image[0x2000..0x2100].iter().all(|&b| b == 0xaa));
+        assert_eq!(image[0x2100..0x2110], [1, 2].repeat(8)[..]);
+        assert!(image[0x2110..].iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn disabled_areas() {
+        let mut areas =
+            read_areas(Path::new("src/testdata/status.dtb"), &AreaOpts::default()).unwrap();
+        let labels: Vec<_> = areas.iter().map(|a| a.label()).collect();
+        assert_eq!(labels, ["Boot Blob", "Payload"]);
+
+        // The Payload is placed right after the Boot Blob, and the
+        // disabled area's fill is nowhere.
+        let image = pack(&mut areas, &LayoutOpts::default()).unwrap();
+        assert_eq!(areas[1].offset, Some(0x100));
+        assert_eq!(image, [0xff; 0x200]);
+    }
+
+    #[test]
+    fn root_metadata() {
+        let meta =
+            read_metadata(Path::new("src/testdata/metadata.dtb"), &AreaOpts::default()).unwrap();
+        assert_eq!(
+            meta,
+            Metadata {
+                model: Some("oreboot test board".to_string()),
+                layout_version: Some(2),
+            }
+        );
+        static DATA: &[u8] = include_bytes!("testdata/unnamed.dtb");
+        let meta = metadata(&fdt::Fdt::new(DATA).unwrap()).unwrap();
+        assert_eq!(meta, Metadata::default());
+    }
+
+    #[test]
+    fn unknown_type() {
+        static DATA: &[u8] = include_bytes!("testdata/schema.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let node = fdt.find_node("/flash-info/areas/area@3").unwrap();
+        let mut a = Area::default();
+        let err = set_property(&mut a, &node.property("type").unwrap()).unwrap_err();
+        assert!(
+            err.to_string().contains("raw, stage, payload, fsp"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn pad_file_not_a_string() {
+        static DATA: &[u8] = include_bytes!("testdata/schema.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let node = fdt.find_node("/flash-info/areas/group@0/area@2").unwrap();
+        let mut a = Area::default();
+        let err = set_property(&mut a, &node.property("pad-file").unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area '': property 'pad-file' should be a string, but is 2 bytes long"
+        );
+    }
+
+    #[test]
+    fn unknown_compression() {
+        static DATA: &[u8] = include_bytes!("testdata/schema.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let node = fdt.find_node("/flash-info/areas/area@0").unwrap();
+        let mut a = Area::default();
+        let err = set_property(&mut a, &node.property("compression").unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area '': property 'compression' should be one of gzip, lz4, lzma, zstd, but is 'xz'"
+        );
+    }
+
+    #[test]
+    fn empty_fill_pattern() {
+        static DATA: &[u8] = include_bytes!("testdata/schema.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let node = fdt.find_node("/flash-info/areas/area@4").unwrap();
+        let mut a = Area::default();
+        let err = set_property(&mut a, &node.property("fill-pattern").unwrap()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area '': property 'fill-pattern' should be a non-empty byte string, but is empty"
+        );
+    }
+
+    #[test]
+    fn end_property() {
+        let mut a = area("area@0", Some(0x1000), 0);
+        a.end = Some(0x3000);
+        resolve_end(&mut a, false).unwrap();
+        assert_eq!(a.size, 0x2000);
+        // A matching size is fine.
+        resolve_end(&mut a, true).unwrap();
+
+        let mut a = area("area@0", Some(0x1000), 0x1000);
+        a.end = Some(0x3000);
+        assert!(resolve_end(&mut a, true).is_err());
+
+        let mut a = area("area@0", Some(0x1000), 0);
+        a.end = Some(0x1000);
+        assert!(resolve_end(&mut a, false).is_err());
+
+        let mut a = area("area@0", None, 0);
+        a.end = Some(0x1000);
+        assert!(resolve_end(&mut a, false).is_err());
+    }
+
+    #[test]
+    fn strict_needs_a_name() {
+        static DATA: &[u8] = include_bytes!("testdata/unnamed.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let err = create_areas(
+            &fdt,
+            &AreaOpts {
+                strict: true,
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("area@100"), "{}", err);
+
+        let areas = create_areas(&fdt, &AreaOpts::default()).unwrap();
+        assert_eq!(areas[0].description.as_deref(), Some("Boot Blob"));
+        assert_eq!(areas[1].description.as_deref(), Some("100"));
+    }
+
+    #[test]
+    fn malformed_property() {
+        static DATA: &[u8] = include_bytes!("testdata/truncated.dtb");
+        let fdt = fdt::Fdt::new(DATA).unwrap();
+        let err = create_areas(&fdt, &AreaOpts::default()).unwrap_err();
+        assert!(matches!(err, LayoutError::Area(_)));
+        assert_eq!(
+            err.to_string(),
+            "Area 'area@0': property 'size' should be a 32 or 64 bit number, but is 2 bytes long"
+        );
+    }
+}
@@ -0,0 +1,90 @@
+use crate::area::Area;
+use crate::error::{LayoutError, Result};
+
+// The `compatible` of an Area that layout_flash fills with an area index.
+pub const COMPATIBLE: &str = "oreboot,area-index";
+
+pub const ENTRY_SIZE: usize = 12;
+
+// The area index lets the earliest boot stage find Areas without an fdt
+// parser. It is a table of little-endian u32s:
+//   count
+//   count entries of: name hash, offset, size
+// The entries are in DTS order, and include the index Area itself. The
+// name hash is the 32 bit FNV-1a hash of the Area's label, see name_hash.
+// It is an error if the index doesn't fit in its Area, `index`.
+pub fn area_index(index: &Area, areas: &[Area]) -> Result<Vec<u8>> {
+    let word = |a: &Area, what: &str, x: usize| {
+        u32::try_from(x).map_err(|_| {
+            LayoutError::Layout(format!(
+                "Area '{}': the {} {:#x} doesn't fit in an area index",
+                a.label(),
+                what,
+                x
+            ))
+        })
+    };
+    let mut v = Vec::with_capacity(4 + areas.len() * ENTRY_SIZE);
+    v.extend_from_slice(&(areas.len() as u32).to_le_bytes());
+    for a in areas {
+        v.extend_from_slice(&name_hash(a.label()).to_le_bytes());
+        v.extend_from_slice(&word(a, "offset", a.offset.unwrap())?.to_le_bytes());
+        v.extend_from_slice(&word(a, "size", a.size)?.to_le_bytes());
+    }
+    if v.len() > index.capacity() {
+        return Err(LayoutError::Layout(format!(
+            "Area '{}': the area index needs {} bytes, but the area is {} bytes",
+            index.label(),
+            v.len(),
+            index.capacity()
+        )));
+    }
+    Ok(v)
+}
+
+// name_hash: the 32 bit FNV-1a hash, simple enough to write in assembly.
+pub fn name_hash(name: &str) -> u32 {
+    name.bytes()
+        .fold(0x811c9dc5, |h, b| (h ^ b as u32).wrapping_mul(0x01000193))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{pack, LayoutOpts};
+    use crate::testutil::area;
+
+    #[test]
+    fn parse_index() {
+        let mut areas = vec![
+            area("area@0", Some(0), 0x100),
+            area("area@1", Some(0x100), 0x40),
+            area("area@2", Some(0x1000), 0x2000),
+        ];
+        areas[0].description = Some("Boot Blob".to_string());
+        areas[1].compatible = Some(COMPATIBLE.to_string());
+        let image = pack(&mut areas, &LayoutOpts::default()).unwrap();
+
+        let word = |i: usize| u32::from_le_bytes(image[i..i + 4].try_into().unwrap());
+        let count = word(0x100) as usize;
+        let entries: Vec<_> = (0..count)
+            .map(|i| 0x104 + i * ENTRY_SIZE)
+            .map(|e| (word(e), word(e + 4) as usize, word(e + 8) as usize))
+            .collect();
+        let want: Vec<_> = areas
+            .iter()
+            .map(|a| (name_hash(a.label()), a.offset.unwrap(), a.size))
+            .collect();
+        assert_eq!(entries, want);
+        assert_eq!(name_hash(""), 0x811c9dc5);
+        assert_eq!(name_hash("a"), 0xe40c292c);
+
+        // Three entries don't fit in 0x20 bytes.
+        areas[1].size = 0x20;
+        let err = pack(&mut areas, &LayoutOpts::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area 'area@1': the area index needs 40 bytes, but the area is 32 bytes"
+        );
+    }
+}
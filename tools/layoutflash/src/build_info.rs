@@ -0,0 +1,94 @@
+use crate::error::{LayoutError, Result};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// The `compatible` of an Area that layout_flash fills with a build-info blob.
+pub const COMPATIBLE: &str = "oreboot,build-info";
+
+pub const MAGIC: &[u8; 8] = b"OREBUILD";
+pub const VERSION_LEN: usize = 32;
+pub const GIT_HASH_LEN: usize = 64;
+pub const SIZE: usize = 24 + VERSION_LEN + GIT_HASH_LEN;
+
+// The build-info blob has a fixed layout, so that runtime code can read it
+// without parsing anything. All integers are little-endian, strings are
+// NUL-padded and need not be NUL-terminated when they fill their field.
+//
+//   0x00  [u8; 8]   magic, "OREBUILD"
+//   0x08  u32       layout version, currently 1
+//   0x0c  u32       reserved, 0
+//   0x10  u64       build time in seconds since the Unix epoch
+//   0x18  [u8; 32]  layoutflash version
+//   0x38  [u8; 64]  git hash, all NULs if not given
+//
+// The build time is taken from SOURCE_DATE_EPOCH if it is set, so that
+// reproducible builds produce identical images. Otherwise it is the
+// current time, or 0 if `reproducible`.
+pub fn build_info(git_hash: Option<&str>, reproducible: bool) -> Result<Vec<u8>> {
+    build_info_at(git_hash, build_time(reproducible)?)
+}
+
+// build_info_at: the build-info blob for a given build time.
+fn build_info_at(git_hash: Option<&str>, time: u64) -> Result<Vec<u8>> {
+    let mut blob = Vec::with_capacity(SIZE);
+    blob.extend_from_slice(MAGIC);
+    blob.extend_from_slice(&1u32.to_le_bytes());
+    blob.extend_from_slice(&0u32.to_le_bytes());
+    blob.extend_from_slice(&time.to_le_bytes());
+    push_padded(&mut blob, env!("CARGO_PKG_VERSION"), VERSION_LEN, "version")?;
+    push_padded(&mut blob, git_hash.unwrap_or(""), GIT_HASH_LEN, "git hash")?;
+    Ok(blob)
+}
+
+pub(crate) fn build_time(reproducible: bool) -> Result<u64> {
+    epoch_time(
+        std::env::var("SOURCE_DATE_EPOCH").ok().as_deref(),
+        reproducible,
+    )
+}
+
+// epoch_time: build_time for a SOURCE_DATE_EPOCH, if there is one.
+fn epoch_time(epoch: Option<&str>, reproducible: bool) -> Result<u64> {
+    match epoch {
+        Some(epoch) => epoch.parse().map_err(|e| {
+            LayoutError::Input(format!(
+                "SOURCE_DATE_EPOCH '{}' is not a timestamp: {}",
+                epoch, e
+            ))
+        }),
+        None if reproducible => Ok(0),
+        None => Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs())),
+    }
+}
+
+pub(crate) fn push_padded(blob: &mut Vec<u8>, s: &str, len: usize, what: &str) -> Result<()> {
+    if s.len() > len {
+        return Err(LayoutError::Input(format!(
+            "The {} '{}' is longer than {} bytes",
+            what, s, len
+        )));
+    }
+    blob.extend_from_slice(s.as_bytes());
+    blob.resize(blob.len() + len - s.len(), 0);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reproducible() {
+        let time = epoch_time(Some("1700000000"), false).unwrap();
+        let blob = build_info_at(Some("0123abcd"), time).unwrap();
+        assert_eq!(blob.len(), SIZE);
+        assert_eq!(&blob[..8], MAGIC);
+        assert_eq!(blob[0x10..0x18], 1700000000u64.to_le_bytes());
+        assert_eq!(&blob[0x38..0x40], b"0123abcd");
+        assert!(blob[0x40..].iter().all(|b| *b == 0));
+
+        assert_eq!(epoch_time(None, true).unwrap(), 0);
+        assert!(epoch_time(Some("yesterday"), true).is_err());
+    }
+}
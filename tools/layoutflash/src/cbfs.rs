@@ -0,0 +1,42 @@
+use crate::area::Area;
+
+// The CBFS file types an Area's `type` property may name, with the
+// constants coreboot's cbfstool uses for them. An Area without a `type`
+// is raw.
+pub const TYPES: &[(&str, u32)] = &[
+    ("raw", 0x50),
+    ("stage", 0x10),
+    ("payload", 0x20),
+    ("fsp", 0x60),
+];
+
+// cbfs_type: the CBFS file type constant for a `type` name.
+pub fn cbfs_type(name: &str) -> Option<u32> {
+    TYPES.iter().find(|(n, _)| *n == name).map(|(_, t)| *t)
+}
+
+// area_type: the CBFS file type of an Area. `type` is checked when the
+// Area is read, so it is always known.
+pub fn area_type(a: &Area) -> u32 {
+    cbfs_type(a.type_.as_deref().unwrap_or("raw")).unwrap()
+}
+
+// type_names: the known `type` names, for error messages.
+pub fn type_names() -> String {
+    TYPES.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::area;
+
+    #[test]
+    fn types() {
+        let mut a = area("area@0", Some(0), 0x100);
+        assert_eq!(area_type(&a), 0x50);
+        a.type_ = Some("payload".to_string());
+        assert_eq!(area_type(&a), 0x20);
+        assert_eq!(cbfs_type("elf"), None);
+    }
+}
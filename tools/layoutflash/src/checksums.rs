@@ -0,0 +1,131 @@
+use crate::area::Area;
+use crate::area_index::name_hash;
+use crate::error::{LayoutError, Result};
+use sha2::{Digest, Sha256};
+
+// The `compatible` of an Area that layout_flash fills with the checksums
+// of the Areas named in its `covers` property.
+pub const COMPATIBLE: &str = "oreboot,checksums";
+
+pub const ENTRY_SIZE: usize = 40;
+
+// The checksum table lets a later stage check several Areas against one
+// table at run time. It is
+//   count                       little-endian u32
+//   count entries of:
+//     name hash, size           little-endian u32s, as in the area index
+//     SHA-256 of the contents   32 bytes
+// with the entries in `covers` order. The contents are those the covered
+// Area was left with once every other Area had been written.
+pub fn checksums(a: &Area, covered: &[(&Area, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut v = Vec::with_capacity(4 + covered.len() * ENTRY_SIZE);
+    v.extend_from_slice(&(covered.len() as u32).to_le_bytes());
+    for (c, contents) in covered {
+        let size = u32::try_from(c.size).map_err(|_| {
+            LayoutError::Layout(format!(
+                "Area '{}': the size {:#x} doesn't fit in a checksum table",
+                c.label(),
+                c.size
+            ))
+        })?;
+        v.extend_from_slice(&name_hash(c.label()).to_le_bytes());
+        v.extend_from_slice(&size.to_le_bytes());
+        v.extend_from_slice(&Sha256::digest(contents));
+    }
+    if v.len() > a.capacity() {
+        return Err(LayoutError::Layout(format!(
+            "Area '{}' needs {} bytes for the checksums of {} areas, but holds {}",
+            a.label(),
+            v.len(),
+            covered.len(),
+            a.capacity()
+        )));
+    }
+    Ok(v)
+}
+
+// covered: the Areas a checksum Area covers, in `covers` order. An Area
+// can't cover a checksum Area, whose table isn't written until the end.
+pub fn covered<'a>(a: &Area, areas: &'a [Area]) -> Result<Vec<&'a Area>> {
+    a.covers
+        .iter()
+        .map(|name| {
+            let c = areas
+                .iter()
+                .find(|c| c.name == *name || c.label() == name)
+                .ok_or_else(|| {
+                    LayoutError::Layout(format!(
+                        "Area '{}' covers '{}', but there is no such area",
+                        a.label(),
+                        name
+                    ))
+                })?;
+            if c.compatible.as_deref() == Some(COMPATIBLE) {
+                return Err(LayoutError::Layout(format!(
+                    "Area '{}' can't cover the checksum area '{}'",
+                    a.label(),
+                    c.label()
+                )));
+            }
+            Ok(c)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{pack, LayoutOpts};
+    use crate::testutil::{area, temp_path};
+    use std::fs;
+
+    #[test]
+    fn parse_checksums() {
+        let file = temp_path("checksummed");
+        fs::write(&file, b"bootblob").unwrap();
+        let mut areas = vec![
+            area("area@0", Some(0), 0x100),
+            area("area@1", Some(0x100), 0x80),
+            area("area@2", Some(0x180), 0x200),
+        ];
+        areas[0].description = Some("Boot Blob".to_string());
+        areas[0].file = Some(file.to_str().unwrap().to_string());
+        areas[1].compatible = Some(COMPATIBLE.to_string());
+        areas[1].covers = vec!["area@2".to_string(), "Boot Blob".to_string()];
+        areas[2].fill = Some(0);
+        let image = pack(&mut areas, &LayoutOpts::default()).unwrap();
+
+        let word = |i: usize| u32::from_le_bytes(image[i..i + 4].try_into().unwrap());
+        assert_eq!(word(0x100), 2);
+        let entries: Vec<_> = (0..2)
+            .map(|i| 0x104 + i * ENTRY_SIZE)
+            .map(|e| (word(e), word(e + 4) as usize, image[e + 8..e + 40].to_vec()))
+            .collect();
+        let want: Vec<_> = [&areas[2], &areas[0]]
+            .iter()
+            .map(|a| {
+                let offset = a.offset.unwrap();
+                let contents = &image[offset..offset + a.size];
+                (
+                    name_hash(a.label()),
+                    a.size,
+                    Sha256::digest(contents).to_vec(),
+                )
+            })
+            .collect();
+        assert_eq!(entries, want);
+        assert_eq!(
+            image[..8],
+            *b"bootblob",
+            "the boot blob was written before it was hashed"
+        );
+
+        // Two entries don't fit in 0x40 bytes.
+        areas[1].size = 0x40;
+        let err = pack(&mut areas, &LayoutOpts::default()).unwrap_err();
+        assert!(err.to_string().contains("needs 84 bytes"), "{}", err);
+        areas[1].covers = vec!["area@3".to_string()];
+        assert!(pack(&mut areas, &LayoutOpts::default()).is_err());
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,66 @@
+use crate::error::Result;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// Compression: how the whole image is wrapped for delivery, after it has
+// been laid out uncompressed.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    Gz,
+    Zstd,
+}
+
+impl Compression {
+    // path: the output path with the compression's extension appended,
+    // e.g. out.bin.gz.
+    pub fn path(&self, out: &Path) -> PathBuf {
+        let ext = match self {
+            Compression::Gz => "gz",
+            Compression::Zstd => "zst",
+        };
+        let mut path = out.as_os_str().to_owned();
+        path.push(".");
+        path.push(ext);
+        PathBuf::from(path)
+    }
+}
+
+pub fn compress(image: &[u8], c: Compression) -> Result<Vec<u8>> {
+    Ok(match c {
+        Compression::Gz => {
+            let mut e = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+            e.write_all(image)?;
+            e.finish()?
+        }
+        Compression::Zstd => zstd::encode_all(image, 19)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn round_trip() {
+        let image: Vec<u8> = (0..0x1000).map(|i| (i % 7) as u8).collect();
+
+        let mut gz = Vec::new();
+        flate2::read::GzDecoder::new(&compress(&image, Compression::Gz).unwrap()[..])
+            .read_to_end(&mut gz)
+            .unwrap();
+        assert_eq!(gz, image);
+
+        let zst = zstd::decode_all(&compress(&image, Compression::Zstd).unwrap()[..]).unwrap();
+        assert_eq!(zst, image);
+
+        assert_eq!(
+            Compression::Gz.path(Path::new("out.bin")),
+            Path::new("out.bin.gz")
+        );
+        assert_eq!(
+            Compression::Zstd.path(Path::new("out.bin")),
+            Path::new("out.bin.zst")
+        );
+    }
+}
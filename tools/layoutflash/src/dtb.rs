@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+// DtbWriter: a minimal flattened device tree writer, enough to emit the
+// trees layoutflash reads. Nodes are opened and closed in order, and
+// properties belong to the innermost open node:
+//     let mut w = DtbWriter::new();
+//     w.begin_node("");
+//     w.property_u32("#address-cells", 1);
+//     w.end_node();
+//     let dtb = w.finish();
+// finish writes a version 17 header with an empty memory reservation map.
+#[derive(Default)]
+pub struct DtbWriter {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    string_offsets: HashMap<String, u32>,
+}
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+const HEADER_SIZE: usize = 40;
+const RESERVE_MAP_SIZE: usize = 16;
+
+impl DtbWriter {
+    pub fn new() -> DtbWriter {
+        DtbWriter::default()
+    }
+
+    pub fn begin_node(&mut self, name: &str) {
+        self.word(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+    }
+
+    pub fn end_node(&mut self) {
+        self.word(FDT_END_NODE);
+    }
+
+    pub fn property(&mut self, name: &str, value: &[u8]) {
+        let offset = match self.string_offsets.get(name) {
+            Some(offset) => *offset,
+            None => {
+                let offset = self.strings.len() as u32;
+                self.strings.extend_from_slice(name.as_bytes());
+                self.strings.push(0);
+                self.string_offsets.insert(name.to_string(), offset);
+                offset
+            }
+        };
+        self.word(FDT_PROP);
+        self.word(value.len() as u32);
+        self.word(offset);
+        self.structure.extend_from_slice(value);
+        self.pad();
+    }
+
+    pub fn property_u32(&mut self, name: &str, value: u32) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    pub fn property_u64(&mut self, name: &str, value: u64) {
+        self.property(name, &value.to_be_bytes());
+    }
+
+    pub fn property_str(&mut self, name: &str, value: &str) {
+        let mut v = value.as_bytes().to_vec();
+        v.push(0);
+        self.property(name, &v);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.word(FDT_END);
+        let off_struct = HEADER_SIZE + RESERVE_MAP_SIZE;
+        let off_strings = off_struct + self.structure.len();
+        let total = off_strings + self.strings.len();
+        let mut dtb = Vec::with_capacity(total);
+        for word in [
+            FDT_MAGIC,
+            total as u32,
+            off_struct as u32,
+            off_strings as u32,
+            HEADER_SIZE as u32,
+            17,
+            16,
+            0,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ] {
+            dtb.extend_from_slice(&word.to_be_bytes());
+        }
+        dtb.resize(off_struct, 0);
+        dtb.extend_from_slice(&self.structure);
+        dtb.extend_from_slice(&self.strings);
+        dtb
+    }
+
+    fn word(&mut self, w: u32) {
+        self.structure.extend_from_slice(&w.to_be_bytes());
+    }
+
+    fn pad(&mut self) {
+        while self.structure.len() % 4 != 0 {
+            self.structure.push(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readable() {
+        let mut w = DtbWriter::new();
+        w.begin_node("");
+        w.property_str("model", "test");
+        w.begin_node("child@1");
+        w.property_u32("size", 0x100);
+        w.property_u64("offset", 0x1_0000_0000);
+        w.end_node();
+        w.end_node();
+        let dtb = w.finish();
+        let fdt = fdt::Fdt::new(&dtb).unwrap();
+        assert_eq!(fdt.total_size(), dtb.len());
+        assert_eq!(fdt.root().model(), "test");
+        let child = fdt.find_node("/child@1").unwrap();
+        assert_eq!(child.property("size").unwrap().as_usize(), Some(0x100));
+        assert_eq!(
+            child.property("offset").unwrap().as_usize(),
+            Some(0x1_0000_0000)
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use std::{error, fmt, io};
+
+/// The errors reading Areas and laying out an image can produce.
+///
+/// ```
+/// use layoutflash_tool::{compute_layout, Area, LayoutError, LayoutOpts};
+///
+/// fn place(areas: &mut [Area]) -> layoutflash_tool::Result<()> {
+///     compute_layout(areas, &LayoutOpts::default())
+/// }
+///
+/// let mut areas = [Area {
+///     name: "area@0".to_string(),
+///     size: 0x100,
+///     align: Some(0),
+///     ..Default::default()
+/// }];
+/// match place(&mut areas) {
+///     Err(LayoutError::Layout(msg)) => assert_eq!(msg, "Area 'area@0' has an alignment of 0"),
+///     other => panic!("unexpected {:?}", other),
+/// }
+/// ```
+#[derive(Debug)]
+pub enum LayoutError {
+    /// Reading or writing the image or the fdt failed.
+    Io(io::Error),
+    /// An Area's file could not be read.
+    Open(String, io::Error),
+    /// An fdt could not be parsed.
+    Fdt(String),
+    /// An Area is badly defined in the fdt.
+    Area(String),
+    /// The Areas can't be laid out as described.
+    Layout(String),
+    /// A command line option or environment variable is invalid.
+    Input(String),
+    /// Warnings were logged and --fail-on-warning is set.
+    Warnings(usize),
+    /// More than one of the above, e.g. every problem with a layout.
+    Multiple(Vec<LayoutError>),
+}
+
+pub type Result<T> = std::result::Result<T, LayoutError>;
+
+impl fmt::Display for LayoutError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LayoutError::Io(e) => write!(f, "{}", e),
+            LayoutError::Open(path, _) => write!(f, "Could not open: {}", path),
+            LayoutError::Fdt(msg)
+            | LayoutError::Area(msg)
+            | LayoutError::Layout(msg)
+            | LayoutError::Input(msg) => f.write_str(msg),
+            LayoutError::Warnings(n) => write!(f, "{} warning(s) with --fail-on-warning", n),
+            LayoutError::Multiple(errors) => {
+                write!(f, "{} errors: {}", errors.len(), self.messages().join("; "))
+            }
+        }
+    }
+}
+
+impl LayoutError {
+    // from_all: Ok if there are no errors, the error if there is one,
+    // and Multiple if there are more.
+    pub fn from_all(mut errors: Vec<LayoutError>) -> Result<()> {
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0)),
+            _ => Err(LayoutError::Multiple(errors)),
+        }
+    }
+
+    // messages: the message of each error, one per error in Multiple.
+    pub fn messages(&self) -> Vec<String> {
+        match self {
+            LayoutError::Multiple(errors) => errors.iter().flat_map(|e| e.messages()).collect(),
+            e => vec![e.to_string()],
+        }
+    }
+}
+
+impl error::Error for LayoutError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LayoutError::Io(e) | LayoutError::Open(_, e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LayoutError {
+    fn from(e: io::Error) -> Self {
+        LayoutError::Io(e)
+    }
+}
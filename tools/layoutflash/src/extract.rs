@@ -0,0 +1,87 @@
+use crate::area::Area;
+use crate::error::{LayoutError, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+// sanitize: map an Area label to a portable file name. Path separators,
+// whitespace and anything else unusual become '_', and a leading '.' is
+// replaced so that the name can't be hidden or mean a parent directory.
+pub fn sanitize(label: &str) -> String {
+    let mut name: String = label
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect();
+    if name.starts_with('.') {
+        name.replace_range(..1, "_");
+    }
+    if name.is_empty() {
+        name.push_str("area");
+    }
+    name
+}
+
+// file_names: a unique sanitized file name for each Area, in order.
+// Later Areas whose names collide get a numeric suffix, _2, _3 and so on.
+pub fn file_names(areas: &[Area]) -> Vec<String> {
+    let mut used = HashSet::new();
+    areas
+        .iter()
+        .map(|a| {
+            let base = sanitize(a.label());
+            let mut name = base.clone();
+            let mut n = 1;
+            while !used.insert(name.clone()) {
+                n += 1;
+                name = format!("{}_{}", base, n);
+            }
+            name
+        })
+        .collect()
+}
+
+// extract: write each laid out Area's bytes from the image to its own
+// <name>.bin file in dir.
+pub fn extract(areas: &[Area], image: &[u8], dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    for (a, name) in areas.iter().zip(file_names(areas)) {
+        let offset = a.offset.unwrap();
+        let data = image.get(offset..offset + a.size).ok_or_else(|| {
+            LayoutError::Layout(format!(
+                "Area '{}' ends at {:#x}, past the end of the {:#x} byte image",
+                a.name,
+                offset + a.size,
+                image.len()
+            ))
+        })?;
+        fs::write(dir.join(format!("{}.bin", name)), data)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::area;
+
+    #[test]
+    fn sanitized_names() {
+        assert_eq!(sanitize("coreboot/fallback"), "coreboot_fallback");
+        assert_eq!(sanitize("Boot Blob\tv2"), "Boot_Blob_v2");
+        assert_eq!(sanitize("../up"), "_._up");
+        assert_eq!(sanitize(""), "area");
+
+        let mut areas = vec![
+            area("area@0", Some(0), 1),
+            area("area@1", Some(1), 1),
+            area("area@2", Some(2), 1),
+        ];
+        for a in &mut areas {
+            a.description = Some("RW A".to_string());
+        }
+        assert_eq!(file_names(&areas), ["RW_A", "RW_A_2", "RW_A_3"]);
+    }
+}
@@ -0,0 +1,50 @@
+use crate::area::Area;
+use crate::error::Result;
+use crate::extract::file_names;
+use std::io::Write;
+
+// write_flashrom_layout: write the resolved layout as a flashrom layout
+// file, one
+//     0x<START>:0x<END> <NAME>
+// line per Area, with END the inclusive last byte and NAME the Area's
+// extract file name, so that e.g.
+//     flashrom --layout <FILE> --image <NAME> ...
+// programs just that Area. flashrom can't describe an empty region, so
+// zero-size Areas are left out.
+pub fn write_flashrom_layout(w: &mut impl Write, areas: &[Area]) -> Result<()> {
+    for (a, name) in areas.iter().zip(file_names(areas)) {
+        if a.size == 0 {
+            continue;
+        }
+        let start = a.offset.unwrap();
+        writeln!(w, "{:#010x}:{:#010x} {}", start, start + a.size - 1, name)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{compute_layout, LayoutOpts};
+    use crate::testutil::area;
+
+    #[test]
+    fn layout_lines() {
+        let mut areas = vec![
+            area("area@0", Some(0), 0x1000),
+            area("area@1", None, 0),
+            area("area@2", None, 0x20000),
+        ];
+        areas[0].description = Some("Boot Blob".to_string());
+        areas[2].description = Some("payload".to_string());
+        areas[2].align = Some(0x10000);
+        compute_layout(&mut areas, &LayoutOpts::default()).unwrap();
+        let mut out = Vec::new();
+        write_flashrom_layout(&mut out, &areas).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0x00000000:0x00000fff Boot_Blob\n\
+             0x00010000:0x0002ffff payload\n"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::area::Area;
+use crate::error::{LayoutError, Result};
+
+// The `compatible` of an Area that layout_flash fills with an FMAP.
+pub const COMPATIBLE: &str = "oreboot,fmap";
+
+// The FMAP area flags, as defined by flashmap's fmap.h. One bit per flag,
+// so every combination of them is representable:
+//   bit 0  STATIC      the contents don't change once built (`static`)
+//   bit 1  COMPRESSED  the contents are compressed (`compression`)
+//   bit 2  RO          the area is read-only (`read-only`)
+//   bit 3  PRESERVE    keep the contents across updates (`preserve`)
+pub const FMAP_AREA_STATIC: u16 = 1 << 0;
+pub const FMAP_AREA_COMPRESSED: u16 = 1 << 1;
+pub const FMAP_AREA_RO: u16 = 1 << 2;
+pub const FMAP_AREA_PRESERVE: u16 = 1 << 3;
+
+const NAME_LEN: usize = 32;
+
+// flags: the FMAP flags of an Area.
+pub fn flags(a: &Area) -> u16 {
+    let mut flags = 0;
+    if a.static_ {
+        flags |= FMAP_AREA_STATIC;
+    }
+    if a.compression.is_some() {
+        flags |= FMAP_AREA_COMPRESSED;
+    }
+    if a.read_only {
+        flags |= FMAP_AREA_RO;
+    }
+    if a.preserve {
+        flags |= FMAP_AREA_PRESERVE;
+    }
+    flags
+}
+
+// fmap: an FMAP version 1.1 describing the laid out Areas of a flash
+// mapped at base. The layout, packed and little-endian, is
+//   [u8; 8]   signature, "__FMAP__"
+//   u8, u8    version 1.1
+//   u64       base, u32 size, [u8; 32] name
+//   u16       the number of areas, followed by for each area:
+//   u32       offset, u32 size, [u8; 32] name, u16 flags
+// Names are the Areas' labels, NUL-terminated.
+pub fn fmap(areas: &[Area], base: u64) -> Result<Vec<u8>> {
+    let word = |a: &Area, what: &str, x: usize| {
+        u32::try_from(x).map_err(|_| {
+            LayoutError::Layout(format!(
+                "Area '{}': the {} {:#x} doesn't fit in an FMAP",
+                a.label(),
+                what,
+                x
+            ))
+        })
+    };
+    // The flash the FMAP describes ends where the last Area does.
+    let size = match areas.iter().max_by_key(|a| a.offset.unwrap() + a.size) {
+        Some(a) => word(a, "end", a.offset.unwrap() + a.size)?,
+        None => 0,
+    };
+    let mut v = Vec::new();
+    v.extend_from_slice(b"__FMAP__");
+    v.extend_from_slice(&[1, 1]);
+    v.extend_from_slice(&base.to_le_bytes());
+    v.extend_from_slice(&size.to_le_bytes());
+    push_name(&mut v, "FLASH");
+    v.extend_from_slice(&(areas.len() as u16).to_le_bytes());
+    for a in areas {
+        v.extend_from_slice(&word(a, "offset", a.offset.unwrap())?.to_le_bytes());
+        v.extend_from_slice(&word(a, "size", a.size)?.to_le_bytes());
+        push_name(&mut v, a.label());
+        v.extend_from_slice(&flags(a).to_le_bytes());
+    }
+    Ok(v)
+}
+
+// push_name: a NUL-terminated name, truncated to fit its 32 byte field.
+fn push_name(v: &mut Vec<u8>, name: &str) {
+    let name = &name.as_bytes()[..name.len().min(NAME_LEN - 1)];
+    v.extend_from_slice(name);
+    v.resize(v.len() + NAME_LEN - name.len(), 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::area;
+
+    #[test]
+    fn compressed_read_only() {
+        let mut a = area("area@0", Some(0x1000), 0x2000);
+        a.compression = Some("lzma".to_string());
+        a.read_only = true;
+        assert_eq!(flags(&a), FMAP_AREA_COMPRESSED | FMAP_AREA_RO);
+
+        let v = fmap(&[a], 0xff000000).unwrap();
+        // The header is 56 bytes, an area 42.
+        assert_eq!(v.len(), 56 + 42);
+        assert_eq!(v[10..18], 0xff000000u64.to_le_bytes());
+        assert_eq!(v[54..56], [1, 0]);
+        assert_eq!(v[56..60], 0x1000u32.to_le_bytes());
+        assert_eq!(v[60..64], 0x2000u32.to_le_bytes());
+        assert_eq!(&v[64..71], b"area@0\0");
+        assert_eq!(
+            v[96..98],
+            (FMAP_AREA_COMPRESSED | FMAP_AREA_RO).to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn end_too_far() {
+        let a = area("area@0", Some(0xc000_0000), 0x8000_0000);
+        let err = fmap(&[a], 0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area 'area@0': the end 0x140000000 doesn't fit in an FMAP"
+        );
+    }
+}
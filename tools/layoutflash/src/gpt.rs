@@ -0,0 +1,262 @@
+use crate::area::Area;
+use crate::area_index::name_hash;
+use crate::error::{LayoutError, Result};
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+// For eMMC and SD targets, Areas with the `partition` property are disk
+// partitions, and --gpt describes them in a GUID partition table:
+//   LBA 0          a protective MBR
+//   LBA 1          the primary GPT header
+//   LBA 2...       the partition entries, ENTRIES of ENTRY_SIZE bytes
+//   ...            the partitions, at their Areas' offsets
+//   last LBAs      a copy of the entries, then the secondary header
+// The disk is the flash, --flash-size bytes. Each partition's `type-guid`
+// is its partition type, and its name is the Area's label. The disk and
+// partition GUIDs are derived from the labels, so that builds are
+// reproducible.
+pub const ENTRIES: usize = 128;
+pub const ENTRY_SIZE: usize = 128;
+const HEADER_SIZE: usize = 92;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+// parse_guid: the on-disk form of a GUID written as
+// 01234567-89ab-cdef-0123-456789abcdef, whose first three fields are
+// stored little-endian.
+pub fn parse_guid(s: &str) -> Option<[u8; 16]> {
+    let fields: Vec<&str> = s.split('-').collect();
+    let lens: Vec<usize> = fields.iter().map(|f| f.len()).collect();
+    if lens != [8, 4, 4, 4, 12] {
+        return None;
+    }
+    let hex: String = fields.concat();
+    let mut g = [0; 16];
+    for (i, b) in g.iter_mut().enumerate() {
+        *b = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    g[..4].reverse();
+    g[4..6].reverse();
+    g[6..8].reverse();
+    Some(g)
+}
+
+// guid: a version 4 style GUID made from a name.
+fn guid(name: &str) -> [u8; 16] {
+    let mut g = [0; 16];
+    for (i, chunk) in g.chunks_mut(4).enumerate() {
+        chunk.copy_from_slice(&name_hash(&format!("{}/{}", name, i)).to_le_bytes());
+    }
+    g[7] = (g[7] & 0x0f) | 0x40;
+    g[8] = (g[8] & 0x3f) | 0x80;
+    g
+}
+
+// gpt: the tables for a disk of disk_size bytes holding the partition
+// Areas, as (offset, bytes) pieces to write over the image.
+pub fn gpt(areas: &[Area], disk_size: u64, sector_size: u64) -> Result<Vec<(u64, Vec<u8>)>> {
+    let err = |msg: String| Err(LayoutError::Layout(msg));
+    if sector_size < 512 || !sector_size.is_power_of_two() {
+        return err(format!(
+            "The sector size {} is not a power of two of at least 512",
+            sector_size
+        ));
+    }
+    let s = sector_size as usize;
+    let entry_sectors = ((ENTRIES * ENTRY_SIZE + s - 1) / s) as u64;
+    let sectors = disk_size / sector_size;
+    if sectors < 3 + 2 * entry_sectors {
+        return err(format!(
+            "The {:#x} byte disk is too small for a GPT",
+            disk_size
+        ));
+    }
+    let first_usable = 2 + entry_sectors;
+    let last_usable = sectors - 2 - entry_sectors;
+    let usable = first_usable * sector_size..(last_usable + 1) * sector_size;
+
+    for a in areas.iter().filter(|a| a.size > 0) {
+        let (start, end) = (
+            a.offset.unwrap() as u64,
+            (a.offset.unwrap() + a.size) as u64,
+        );
+        if start < usable.start || end > usable.end {
+            return err(format!(
+                "Area '{}' at {:#x}..{:#x} overlaps the GPT; areas must lie within {:#x}..{:#x}",
+                a.label(),
+                start,
+                end,
+                usable.start,
+                usable.end
+            ));
+        }
+    }
+    let partitions: Vec<&Area> = areas.iter().filter(|a| a.partition).collect();
+    if partitions.len() > ENTRIES {
+        return err(format!(
+            "There are {} partitions, but a GPT holds {}",
+            partitions.len(),
+            ENTRIES
+        ));
+    }
+
+    let mut entries = vec![0; ENTRIES * ENTRY_SIZE];
+    for (a, e) in partitions.iter().zip(entries.chunks_mut(ENTRY_SIZE)) {
+        let type_guid = match a.type_guid.as_deref().and_then(parse_guid) {
+            Some(g) => g,
+            None => return err(format!("Partition '{}' needs a type-guid", a.label())),
+        };
+        let offset = a.offset.unwrap() as u64;
+        if offset % sector_size != 0 {
+            return err(format!(
+                "Partition '{}' starts at {:#x}, which is not on a {} byte sector",
+                a.label(),
+                offset,
+                sector_size
+            ));
+        }
+        let first = offset / sector_size;
+        let last = (offset + a.size as u64 + sector_size - 1) / sector_size - 1;
+        e[..16].copy_from_slice(&type_guid);
+        e[16..32].copy_from_slice(&guid(a.label()));
+        e[32..40].copy_from_slice(&first.to_le_bytes());
+        e[40..48].copy_from_slice(&last.to_le_bytes());
+        for (i, c) in a.label().encode_utf16().take(36).enumerate() {
+            e[56 + 2 * i..58 + 2 * i].copy_from_slice(&c.to_le_bytes());
+        }
+    }
+    entries.resize(entry_sectors as usize * s, 0);
+
+    let header = |my_lba: u64, alternate_lba: u64, entries_lba: u64| {
+        let mut h = Vec::with_capacity(s);
+        h.extend_from_slice(b"EFI PART");
+        h.extend_from_slice(&0x0001_0000u32.to_le_bytes());
+        h.extend_from_slice(&(HEADER_SIZE as u32).to_le_bytes());
+        h.extend_from_slice(&[0; 8]);
+        h.extend_from_slice(&my_lba.to_le_bytes());
+        h.extend_from_slice(&alternate_lba.to_le_bytes());
+        h.extend_from_slice(&first_usable.to_le_bytes());
+        h.extend_from_slice(&last_usable.to_le_bytes());
+        h.extend_from_slice(&guid("disk"));
+        h.extend_from_slice(&entries_lba.to_le_bytes());
+        h.extend_from_slice(&(ENTRIES as u32).to_le_bytes());
+        h.extend_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+        h.extend_from_slice(&CRC.checksum(&entries[..ENTRIES * ENTRY_SIZE]).to_le_bytes());
+        let crc = CRC.checksum(&h);
+        h[16..20].copy_from_slice(&crc.to_le_bytes());
+        h.resize(s, 0);
+        h
+    };
+
+    let mut mbr = vec![0; s];
+    let mbr_sectors = u32::try_from(sectors - 1).unwrap_or(u32::MAX);
+    mbr[446..462].copy_from_slice(&[
+        0x00, 0x00, 0x02, 0x00, 0xee, 0xff, 0xff, 0xff, 1, 0, 0, 0, 0, 0, 0, 0,
+    ]);
+    mbr[458..462].copy_from_slice(&mbr_sectors.to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xaa;
+
+    let mut primary = mbr;
+    primary.extend(header(1, sectors - 1, 2));
+    primary.extend_from_slice(&entries);
+    let mut secondary = entries.clone();
+    secondary.extend(header(sectors - 1, 1, sectors - 1 - entry_sectors));
+    Ok(vec![
+        (0, primary),
+        ((sectors - 1 - entry_sectors) * sector_size, secondary),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{pack, LayoutOpts};
+    use crate::testutil::area;
+
+    const LINUX: &str = "0FC63DAF-8483-4772-8E79-3D69D8477DE4";
+
+    fn le64(b: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(b[at..at + 8].try_into().unwrap())
+    }
+
+    fn le32(b: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(b[at..at + 4].try_into().unwrap())
+    }
+
+    // read_gpt: the (type, first, last, name) of each partition in a disk
+    // image, checking the tables as a GPT reader does.
+    fn read_gpt(disk: &[u8], header_lba: u64) -> Vec<([u8; 16], u64, u64, String)> {
+        let h = &disk[header_lba as usize * 512..][..512];
+        assert_eq!(&h[..8], b"EFI PART");
+        assert_eq!(le64(h, 24), header_lba);
+        let mut zeroed = h[..HEADER_SIZE].to_vec();
+        zeroed[16..20].fill(0);
+        assert_eq!(le32(h, 16), CRC.checksum(&zeroed));
+        let entries = &disk[le64(h, 72) as usize * 512..][..ENTRIES * ENTRY_SIZE];
+        assert_eq!(le32(h, 88), CRC.checksum(entries));
+        entries
+            .chunks(ENTRY_SIZE)
+            .filter(|e| e[..16] != [0; 16])
+            .map(|e| {
+                let name: Vec<u16> = e[56..]
+                    .chunks(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|c| *c != 0)
+                    .collect();
+                (
+                    e[..16].try_into().unwrap(),
+                    le64(e, 32),
+                    le64(e, 40),
+                    String::from_utf16(&name).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn partitions_read_back() {
+        let mut boot = area("area@8000", Some(0x8000), 0x10000);
+        boot.description = Some("boot".to_string());
+        let mut root = area("area@20000", Some(0x20000), 0x40000);
+        root.description = Some("rootfs".to_string());
+        for a in [&mut boot, &mut root] {
+            a.partition = true;
+            a.type_guid = Some(LINUX.to_string());
+        }
+        let opts = LayoutOpts {
+            gpt: true,
+            flash_size: Some(0x100000),
+            ..Default::default()
+        };
+        let disk = pack(&mut [boot, root], &opts).unwrap();
+        assert_eq!(disk.len(), 0x100000);
+
+        assert_eq!(disk[450], 0xee);
+        assert_eq!(disk[510..512], [0x55, 0xaa]);
+        let linux = parse_guid(LINUX).unwrap();
+        assert_eq!(linux[..4], [0xaf, 0x3d, 0xc6, 0x0f]);
+        let expected = vec![
+            (linux, 0x40, 0xbf, "boot".to_string()),
+            (linux, 0x100, 0x2ff, "rootfs".to_string()),
+        ];
+        assert_eq!(read_gpt(&disk, 1), expected);
+        assert_eq!(read_gpt(&disk, 2047), expected);
+        let primary = &disk[512..1024];
+        assert_eq!(le64(primary, 32), 2047);
+        assert_eq!(le64(primary, 40), 34);
+        assert_eq!(le64(primary, 48), 2014);
+    }
+
+    #[test]
+    fn bad_partitions() {
+        let mut a = area("area@0", Some(0), 0x1000);
+        a.partition = true;
+        a.type_guid = Some(LINUX.to_string());
+        // Over the primary table.
+        assert!(gpt(&[a.clone()], 0x100000, 512).is_err());
+        a.offset = Some(0x8000);
+        a.type_guid = None;
+        assert!(gpt(&[a], 0x100000, 512).is_err());
+        assert_eq!(parse_guid("0FC63DAF-8483-4772-8E79"), None);
+    }
+}
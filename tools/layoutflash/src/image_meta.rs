@@ -0,0 +1,84 @@
+use crate::area::read_file;
+use crate::build_info::{build_time, push_padded, VERSION_LEN};
+use crate::error::Result;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+
+// The `compatible` of an Area that layout_flash fills with an image-meta
+// blob, which ties an image to the layout it was built from.
+pub const COMPATIBLE: &str = "oreboot,image-meta";
+
+pub const MAGIC: &[u8; 8] = b"OREIMAGE";
+pub const SIZE: usize = 0x58;
+pub const REPRODUCIBLE_SIZE: usize = 0x50;
+
+// The image-meta blob has a fixed layout, all integers little-endian:
+//
+//   0x00  [u8; 8]   magic, "OREIMAGE"
+//   0x08  u16       layout version, currently 1
+//   0x0a  u16       size of the blob, SIZE or REPRODUCIBLE_SIZE
+//   0x0c  u32       number of Areas in the layout
+//   0x10  [u8; 32]  layoutflash version, NUL-padded
+//   0x30  [u8; 32]  SHA-256 of the input fdt, all 0 if not known
+//   0x50  u64       build time in seconds since the Unix epoch
+//
+// With `reproducible` the blob stops before the build time, so that it
+// holds only what the inputs determine.
+pub fn image_meta(
+    fdt_hash: Option<&[u8; 32]>,
+    areas: usize,
+    reproducible: bool,
+) -> Result<Vec<u8>> {
+    let size = if reproducible {
+        REPRODUCIBLE_SIZE
+    } else {
+        SIZE
+    };
+    let mut blob = Vec::with_capacity(size);
+    blob.extend_from_slice(MAGIC);
+    blob.extend_from_slice(&1u16.to_le_bytes());
+    blob.extend_from_slice(&(size as u16).to_le_bytes());
+    blob.extend_from_slice(&(areas as u32).to_le_bytes());
+    push_padded(&mut blob, env!("CARGO_PKG_VERSION"), VERSION_LEN, "version")?;
+    blob.extend_from_slice(fdt_hash.unwrap_or(&[0; 32]));
+    if !reproducible {
+        blob.extend_from_slice(&build_time(false)?.to_le_bytes());
+    }
+    Ok(blob)
+}
+
+// fdt_hash: the SHA-256 of an fdt file, for LayoutOpts::fdt_hash.
+pub fn fdt_hash(path: &Path) -> Result<[u8; 32]> {
+    Ok(Sha256::digest(read_file(&path.to_string_lossy())?).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{pack, LayoutOpts};
+    use crate::testutil::area;
+
+    #[test]
+    fn parse_image_meta() {
+        let mut areas = vec![area("area@0", None, 0x40), area("area@1", None, 0x100)];
+        areas[1].compatible = Some(COMPATIBLE.to_string());
+        let opts = LayoutOpts {
+            fdt_hash: Some(fdt_hash(Path::new("src/testdata/test.dtb")).unwrap()),
+            ..Default::default()
+        };
+        let image = pack(&mut areas, &opts).unwrap();
+        let blob = &image[0x40..0x40 + SIZE];
+
+        assert_eq!(&blob[..8], MAGIC);
+        assert_eq!(blob[0x08..0x0c], [1, 0, SIZE as u8, 0]);
+        assert_eq!(blob[0x0c..0x10], 2u32.to_le_bytes());
+        assert!(blob[0x10..0x30].starts_with(env!("CARGO_PKG_VERSION").as_bytes()));
+        let want: [u8; 32] = Sha256::digest(include_bytes!("testdata/test.dtb")).into();
+        assert_eq!(blob[0x30..0x50], want);
+
+        // Only the fields the inputs determine, and no build time.
+        let blob = image_meta(Some(&want), 3, true).unwrap();
+        assert_eq!(blob.len(), REPRODUCIBLE_SIZE);
+        assert_eq!(blob, image_meta(Some(&want), 3, true).unwrap());
+    }
+}
@@ -0,0 +1,54 @@
+use crate::area::Area;
+use crate::error::Result;
+use crate::extract::file_names;
+use std::io::Write;
+
+// symbol: the Kconfig symbol stem for an Area's extract file name, which
+// is uppercased, with the '-' and '.' Kconfig doesn't allow made '_'.
+pub(crate) fn symbol(file_name: &str) -> String {
+    file_name
+        .chars()
+        .map(|c| match c {
+            '-' | '.' => '_',
+            _ => c.to_ascii_uppercase(),
+        })
+        .collect()
+}
+
+// write_kconfig: write the resolved layout as a defconfig fragment, a
+//     CONFIG_<NAME>_OFFSET=0x...
+//     CONFIG_<NAME>_SIZE=0x...
+// pair per Area, where NAME is the Area's extract file name, uppercased.
+pub fn write_kconfig(w: &mut impl Write, areas: &[Area]) -> Result<()> {
+    for (a, name) in areas.iter().zip(file_names(areas)) {
+        let name = symbol(&name);
+        writeln!(w, "CONFIG_{}_OFFSET={:#x}", name, a.offset.unwrap())?;
+        writeln!(w, "CONFIG_{}_SIZE={:#x}", name, a.size)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::area;
+
+    #[test]
+    fn defconfig_fragment() {
+        let mut areas = vec![
+            area("area@0", Some(0), 0x1000),
+            area("area@1", Some(0x1000), 0x20000),
+        ];
+        areas[0].description = Some("boot-blob".to_string());
+        areas[1].description = Some("Main Payload".to_string());
+        let mut out = Vec::new();
+        write_kconfig(&mut out, &areas).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "CONFIG_BOOT_BLOB_OFFSET=0x0\n\
+             CONFIG_BOOT_BLOB_SIZE=0x1000\n\
+             CONFIG_MAIN_PAYLOAD_OFFSET=0x1000\n\
+             CONFIG_MAIN_PAYLOAD_SIZE=0x20000\n"
+        );
+    }
+}
@@ -0,0 +1,2121 @@
+use crate::area::{expand_path, read_file, Area, FlashInfo, LengthPrefix};
+use crate::area_index::{self, area_index};
+use crate::build_info::{self, build_info};
+use crate::checksums::{self, checksums, covered};
+use crate::compress::{compress, Compression};
+use crate::error::{LayoutError, Result};
+use crate::fmap::{self, fmap};
+use crate::gpt::gpt;
+use crate::image_meta::{self, image_meta};
+use crate::size::parse_size;
+use crate::sniff;
+use crate::trailer::{trailer, TRAILER_SIZE};
+use log::{debug, info, warn};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+// LayoutOpts: the options that control how Areas are placed.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct LayoutOpts {
+    /// Warn instead of failing when areas overlap. Areas are written in
+    /// order of their `index` property, then offset, so later ones win.
+    #[clap(long, global = true)]
+    pub allow_overlap: bool,
+    /// Align every area to this many bytes, unless it has its own `align`
+    #[clap(long, global = true, value_parser = crate::size::parse_size)]
+    pub align_all: Option<u64>,
+    /// Ignore explicit offsets and pack the areas densely in DTS order
+    #[clap(long, global = true)]
+    pub auto_pack: bool,
+    /// The git hash recorded in an `oreboot,build-info` area
+    #[clap(long, global = true)]
+    pub git_hash: Option<String>,
+    /// The most bytes the areas' sizes may add up to [default: 4G]
+    #[clap(long, global = true, value_parser = crate::size::parse_size)]
+    pub max_total_size: Option<u64>,
+    /// Warn about any area bigger than this, e.g. from a mistyped size;
+    /// with --fail-on-warning that fails the build
+    #[clap(long, global = true, value_parser = crate::size::parse_size)]
+    pub max_area_size: Option<u64>,
+    /// The size of the flash part, the top that `anchor = "top"` areas end
+    /// at. Defaults to the size in the `reg` of /flash-info
+    #[clap(long, global = true, value_parser = crate::size::parse_size)]
+    pub flash_size: Option<u64>,
+    /// The address the flash is mapped at, recorded as the FMAP base.
+    /// Defaults to the address in the `reg` of /flash-info, else 0
+    #[clap(long, global = true, value_parser = crate::size::parse_size)]
+    pub base_address: Option<u64>,
+    /// Start from a copy of this image, so that bytes no area covers keep
+    /// their contents
+    #[clap(long, global = true)]
+    pub seed_image: Option<PathBuf>,
+    /// Also write a copy of the whole image starting at this offset, for
+    /// dual image boot schemes. The copy must fit within --flash-size
+    #[clap(long, global = true, value_parser = crate::size::parse_size)]
+    pub mirror_at: Option<u64>,
+    /// Make the output depend only on the inputs: a build-info time of 0
+    /// unless SOURCE_DATE_EPOCH is set, FMAP areas in offset order, and
+    /// only file names, not paths, in JSON reports
+    #[clap(long, global = true)]
+    pub reproducible: bool,
+    /// The flash's program page size. Areas that don't start on a page
+    /// are warned about, or with --strict are an error. Defaults to the
+    /// `page-size` of /flash-info
+    #[clap(long, global = true, value_parser = crate::size::parse_size)]
+    pub page_size: Option<u64>,
+    /// The bank size of dual-bank flash. It is an error for an area to
+    /// cross a bank boundary, even when it is aligned
+    #[clap(long, global = true, value_parser = crate::size::parse_size)]
+    pub bank_size: Option<u64>,
+    /// Move an area, and every area after it, by a number of bytes, as
+    /// <AREA>=+<BYTES> or <AREA>=-<BYTES>; the moved layout is checked again
+    #[clap(long, global = true, value_parser = crate::layout::parse_relocation)]
+    pub relocate: Vec<(String, i64)>,
+    /// Write a protective MBR and GPT describing the `partition` areas,
+    /// for eMMC and SD disks of --flash-size bytes
+    #[clap(long, global = true)]
+    pub gpt: bool,
+    /// The disk sector size for --gpt [default: 512]
+    #[clap(long, global = true, value_parser = crate::size::parse_size)]
+    pub sector_size: Option<u64>,
+    // The SHA-256 of the input fdt, for image-meta Areas.
+    #[clap(skip)]
+    pub fdt_hash: Option<[u8; 32]>,
+    /// Read each area back once it is written, and fail if it doesn't
+    /// hold what was written, e.g. when programming a flash device
+    #[clap(long, global = true)]
+    pub verify_write: bool,
+    // Set from --strict, see AreaOpts.
+    #[clap(skip)]
+    pub strict: bool,
+}
+
+impl LayoutOpts {
+    // with_flash_info: fill in the flash base address, size and page size
+    // from the fdt's /flash-info, unless they were given on the command line.
+    pub fn with_flash_info(&self, info: &FlashInfo) -> LayoutOpts {
+        LayoutOpts {
+            base_address: self.base_address.or(info.reg.map(|r| r.0)),
+            flash_size: self.flash_size.or(info.reg.map(|r| r.1)),
+            page_size: self.page_size.or(info.page_size),
+            ..self.clone()
+        }
+    }
+}
+
+fn round_up(x: usize, align: usize) -> usize {
+    (x + align - 1) / align * align
+}
+
+// align_of: the alignment of an Area's offset, 1 if it has none.
+fn align_of(a: &Area, opts: &LayoutOpts) -> Result<usize> {
+    match a.alignment(opts) {
+        Some(0) => Err(LayoutError::Layout(format!(
+            "Area '{}' has an alignment of 0",
+            a.label()
+        ))),
+        Some(align) => Ok(align),
+        None => Ok(1),
+    }
+}
+
+// In earlier versions of this function, we assumed all Areas had a non-zero
+// offset. There was a sort step to sort by offset as a first step.
+// Requiring users to compute all the offsets, and adjust them every time
+// something changed size, was incredibly inconvenient.
+// Now, we allow a zero offset, but it turns out that sorting is the wrong
+// thing to do: users tend to specify the areas in the dts in the order
+// they need to be in ROM; further, the rule of "put this area here,
+// and everything after it, after it" will not work if we sort: all
+// offset 0 items will be placed first!
+// So, new rules:
+// This method assumes that the areas are specified in order for a reason.
+// In most cases, the offset is 0, meaning "use the previous area end+1
+// as our offset". In rare cases, an offset is specified; for that, ensure
+// there is no overlap with previous Areas. All Areas after this Area will
+// be placed after it. That way, only a limited number of offsets need
+// to be specified, possibly even 0, and the order in the ROM image will be the order
+// specified in the DTS.
+// Zero-size Areas are allowed, e.g. as placeholders. They occupy no space,
+// so they never overlap anything and nothing is written for them, but their
+// offset is checked against the previous Areas like any other, and Areas
+// after them are still placed after them. The next Area may therefore share
+// a zero-size Area's offset.
+// Overlaps are an error unless allow_overlap is set, in which case they
+// are only reported; see layout_flash for the order the Areas are written in.
+// An Area's `align` property, or else --align-all, rounds up the offset
+// of an Area without one; an explicit offset must already be aligned.
+// With auto_pack, explicit offsets are ignored and every Area is placed
+// right after the previous one.
+// Areas with an `anchor` are placed last, see resolve_anchors.
+// The explicit offsets are reserved up front, so an Area without one
+// that would run into an explicitly placed Area later in the DTS skips
+// past it, to the first gap after the previous Area that is big enough.
+// An explicit offset then only needs to keep clear of the Areas placed
+// before it, wherever they ended up. With --flash-size, an Area that no
+// gap in the flash can hold is an error.
+// Every problem found is reported, as LayoutError::Multiple if there is
+// more than one, and the Areas keep the offsets they were given anyway,
+// for display.
+// With --relocate, the laid out Areas are then moved, see relocate, and
+// their new offsets are checked all over again. The warnings are logged
+// once, for the final layout.
+pub fn compute_layout(areas: &mut [Area], opts: &LayoutOpts) -> Result<()> {
+    let mut warnings = vec![];
+    let result = place(areas, opts, &mut warnings).and_then(|()| {
+        if opts.relocate.is_empty() {
+            return Ok(());
+        }
+        if opts.auto_pack {
+            return Err(LayoutError::Input(
+                "--relocate can't be used with --auto-pack".to_string(),
+            ));
+        }
+        for (name, delta) in &opts.relocate {
+            relocate(areas, name, *delta)?;
+        }
+        warnings.clear();
+        place(areas, opts, &mut warnings)
+    });
+    for msg in warnings {
+        warn!("{}", msg);
+    }
+    result
+}
+
+// place: compute_layout without the relocating, collecting the warnings
+// rather than logging them.
+fn place(areas: &mut [Area], opts: &LayoutOpts, warnings: &mut Vec<String>) -> Result<()> {
+    // Refuse runaway inputs before doing any work on them.
+    let max = opts.max_total_size.unwrap_or(4 << 30);
+    let total = areas
+        .iter()
+        .fold(0u64, |t, a| t.saturating_add(a.size as u64));
+    if total > max {
+        return Err(LayoutError::Layout(format!(
+            "The areas add up to {:#x} bytes, more than {:#x}; use --max-total-size to allow more",
+            total, max
+        )));
+    }
+    let mut errors = vec![];
+    warnings.extend(oversized(areas, opts));
+    if let Some(flash_size) = opts.flash_size {
+        for a in areas.iter().filter(|a| a.size as u64 > flash_size) {
+            errors.push(LayoutError::Layout(format!(
+                "Area '{}' is {:#x} bytes, bigger than the whole {:#x} byte flash",
+                a.label(),
+                a.size,
+                flash_size
+            )));
+        }
+    }
+    let reserved: Vec<Range<usize>> = areas
+        .iter()
+        .filter(|a| !opts.auto_pack && a.anchor.is_none() && a.size > 0)
+        .filter_map(|a| a.offset.map(|offset| offset..offset.saturating_add(a.size)))
+        .collect();
+    let mut placed: Vec<Range<usize>> = vec![];
+    let mut last_area_end = 0;
+    for a in areas.iter_mut() {
+        if opts.auto_pack || a.anchor.is_some() {
+            a.offset = None;
+        }
+        if a.anchor.is_some() {
+            continue;
+        }
+        let align = align_of(a, opts).unwrap_or_else(|e| {
+            errors.push(e);
+            1
+        });
+        let offset = match a.offset {
+            Some(x) if x % align != 0 => {
+                errors.push(LayoutError::Layout(format!(
+                    "Area '{}' starts at {:#x}, which is not aligned to {:#x}",
+                    a.label(),
+                    x,
+                    align
+                )));
+                x
+            }
+            Some(x) => x,
+            None => {
+                let offset = fit(round_up(last_area_end, align), a.size, align, &reserved);
+                match opts.flash_size {
+                    Some(flash_size) if (offset + a.size) as u64 > flash_size => {
+                        errors.push(LayoutError::Layout(format!(
+                            "Area '{}' needs {:#x} bytes, but no gap after {:#x} in the {:#x} byte flash is big enough",
+                            a.label(),
+                            a.size,
+                            last_area_end,
+                            flash_size
+                        )))
+                    }
+                    _ => {}
+                }
+                offset
+            }
+        };
+        // The end of the earlier Areas this one would run into.
+        let end = a.offset.and_then(|_| {
+            placed
+                .iter()
+                .filter(|r| r.start < offset.saturating_add(a.size.max(1)) && offset < r.end)
+                .map(|r| r.end)
+                .max()
+        });
+        if let Some(end) = end {
+            let msg = format!("Areas are overlapping, last area finished at offset {}, next area '{}' starts at {}; it must start at {:#x} or later, or use --auto-pack (validate --json suggests offsets)", end, a.label(), offset, round_up(end, align));
+            if !opts.allow_overlap {
+                errors.push(LayoutError::Layout(msg));
+            } else {
+                warnings.push(msg);
+            }
+        }
+        if a.offset.is_some() {
+            errors.extend(past_end(a, offset, opts));
+        }
+        a.offset = Some(offset);
+        placed.push(offset..offset.saturating_add(a.size));
+        last_area_end = last_area_end.max(offset.saturating_add(a.size));
+    }
+    resolve_anchors(areas, opts, &mut errors, warnings);
+    check_pages(areas, opts, &mut errors, warnings);
+    check_banks(areas, opts, &mut errors);
+    LayoutError::from_all(errors)
+}
+
+// past_end: an error if the Area, placed at offset, runs past the end of
+// the --flash-size flash. One bigger than the whole flash is reported as
+// that instead.
+fn past_end(a: &Area, offset: usize, opts: &LayoutOpts) -> Option<LayoutError> {
+    let flash_size = opts.flash_size.filter(|&f| a.size as u64 <= f)?;
+    match offset.checked_add(a.size) {
+        Some(end) if end as u64 <= flash_size => None,
+        _ => Some(LayoutError::Layout(format!(
+            "Area '{}' at {:#x} is {:#x} bytes, and runs past the end of the {:#x} byte flash",
+            a.label(),
+            offset,
+            a.size,
+            flash_size
+        ))),
+    }
+}
+
+// parse_relocation: a --relocate argument, e.g. ramstage=+0x1000.
+pub fn parse_relocation(s: &str) -> std::result::Result<(String, i64), String> {
+    let (name, delta) = s
+        .split_once('=')
+        .ok_or_else(|| format!("'{}' is not <AREA>=+<BYTES> or <AREA>=-<BYTES>", s))?;
+    let (sign, bytes) = match (delta.strip_prefix('+'), delta.strip_prefix('-')) {
+        (Some(bytes), _) => (1, bytes),
+        (_, Some(bytes)) => (-1, bytes),
+        _ => return Err(format!("the delta '{}' needs a + or - sign", delta)),
+    };
+    let bytes = i64::try_from(parse_size(bytes)?)
+        .map_err(|_| format!("the delta '{}' is too large", delta))?;
+    Ok((name.to_string(), sign * bytes))
+}
+
+// relocate: move the laid out Area called name, and every Area at or
+// after its offset, by delta bytes. They are given explicit offsets, so
+// the next compute_layout keeps them there. Areas anchored to another
+// Area follow it anyway, but an Area anchored to the top is pinned there,
+// and is an error to move.
+pub fn relocate(areas: &mut [Area], name: &str, delta: i64) -> Result<()> {
+    let start = areas
+        .iter()
+        .find(|a| a.name == name || a.label() == name)
+        .ok_or_else(|| LayoutError::Input(format!("--relocate: there is no area '{}'", name)))?
+        .offset
+        .unwrap();
+    for a in areas.iter_mut().filter(|a| a.offset.unwrap() >= start) {
+        match a.anchor.as_deref() {
+            Some("top") => {
+                return Err(LayoutError::Layout(format!(
+                    "Area '{}' is anchored to the top, so --relocate {} can't move it",
+                    a.label(),
+                    name
+                )))
+            }
+            Some(_) => {}
+            None => {
+                let offset = usize::try_from(a.offset.unwrap() as i64 + delta).map_err(|_| {
+                    LayoutError::Layout(format!(
+                        "Area '{}' would be moved below the start of the flash",
+                        a.label()
+                    ))
+                })?;
+                a.offset = Some(offset);
+            }
+        }
+    }
+    Ok(())
+}
+
+// oversized: a warning for each Area bigger than --max-area-size.
+pub fn oversized(areas: &[Area], opts: &LayoutOpts) -> Vec<String> {
+    let max = match opts.max_area_size {
+        Some(max) => max,
+        None => return vec![],
+    };
+    areas
+        .iter()
+        .filter(|a| a.size as u64 > max)
+        .map(|a| {
+            format!(
+                "Area '{}' is {:#x} bytes, more than --max-area-size {:#x}",
+                a.label(),
+                a.size,
+                max
+            )
+        })
+        .collect()
+}
+
+// fit: the first aligned offset from start where size bytes don't run
+// into a reserved range.
+fn fit(mut start: usize, size: usize, align: usize, reserved: &[Range<usize>]) -> usize {
+    while let Some(r) = reserved
+        .iter()
+        .find(|r| r.start < start + size && start < r.end)
+    {
+        start = round_up(r.end, align);
+    }
+    start
+}
+
+// resolve_anchors: place the Areas with an `anchor` property, once all
+// the others have been placed. An anchor is either
+//   "top"   the Area ends at --flash-size, rounded down to its alignment
+//   a name  the Area starts where the named Area ends, rounded up;
+//           the name is an Area's node name or its label
+// and replaces any `offset`. Anchors may name anchored Areas, as long as
+// they don't form a cycle. As anchored Areas are placed out of order,
+// they are checked for overlaps against every other Area.
+// An Area whose anchor can't be resolved is left without an offset.
+fn resolve_anchors(
+    areas: &mut [Area],
+    opts: &LayoutOpts,
+    errors: &mut Vec<LayoutError>,
+    warnings: &mut Vec<String>,
+) {
+    let mut failed = vec![false; areas.len()];
+    loop {
+        let mut placed = false;
+        let mut waiting = None;
+        for i in 0..areas.len() {
+            if failed[i] || areas[i].anchor.is_none() || areas[i].offset.is_some() {
+                continue;
+            }
+            match anchor_offset(areas, &areas[i], opts) {
+                Ok(Some(offset)) => {
+                    areas[i].offset = Some(offset);
+                    placed = true;
+                }
+                Ok(None) => waiting = Some(i),
+                Err(e) => {
+                    errors.push(e);
+                    failed[i] = true;
+                }
+            }
+        }
+        match waiting {
+            Some(i) if !placed => {
+                errors.push(LayoutError::Layout(format!(
+                    "The anchors of area '{}' and the areas it is anchored to form a cycle",
+                    areas[i].label()
+                )));
+                break;
+            }
+            Some(_) => {}
+            None => break,
+        }
+    }
+
+    for a in areas.iter().filter(|a| a.anchor.is_some() && a.size > 0) {
+        let offset = match a.offset {
+            Some(offset) => offset,
+            None => continue,
+        };
+        errors.extend(past_end(a, offset, opts));
+        let other = areas.iter().find(|b| {
+            !std::ptr::eq(*b, a)
+                && b.size > 0
+                && b.offset
+                    .map_or(false, |o| o < offset + a.size && offset < o + b.size)
+        });
+        if let Some(b) = other {
+            let msg = format!(
+                "Areas are overlapping, area '{}' is anchored at {:#x}, inside area '{}'",
+                a.label(),
+                offset,
+                b.label()
+            );
+            if !opts.allow_overlap {
+                errors.push(LayoutError::Layout(msg));
+            } else {
+                warnings.push(msg);
+            }
+        }
+    }
+}
+
+// check_pages: report the Areas that don't start on a program page. This
+// is separate from `align`: an Area's alignment is a hard requirement,
+// usually the erase block, while straddling pages only makes programming
+// slower, so it is a warning unless --strict is set.
+fn check_pages(
+    areas: &[Area],
+    opts: &LayoutOpts,
+    errors: &mut Vec<LayoutError>,
+    warnings: &mut Vec<String>,
+) {
+    let page = match opts.page_size {
+        Some(page) if page > 0 => page,
+        _ => return,
+    };
+    for a in areas.iter().filter(|a| a.size > 0) {
+        let offset = match a.offset {
+            Some(offset) if offset as u64 % page != 0 => offset,
+            _ => continue,
+        };
+        let msg = format!(
+            "Area '{}' starts at {:#x}, which is not on a {:#x} byte page",
+            a.label(),
+            offset,
+            page
+        );
+        if opts.strict {
+            errors.push(LayoutError::Layout(msg));
+        } else {
+            warnings.push(msg);
+        }
+    }
+}
+
+// check_banks: an error for each Area that spans the boundary between
+// two --bank-size banks.
+fn check_banks(areas: &[Area], opts: &LayoutOpts, errors: &mut Vec<LayoutError>) {
+    let bank = match opts.bank_size {
+        Some(bank) if bank > 0 => bank,
+        _ => return,
+    };
+    for a in areas.iter().filter(|a| a.size > 0) {
+        let offset = match a.offset {
+            Some(offset) => offset as u64,
+            None => continue,
+        };
+        let end = offset + a.size as u64;
+        if offset / bank != (end - 1) / bank {
+            errors.push(LayoutError::Layout(format!(
+                "Area '{}' at {:#x}..{:#x} crosses the bank boundary at {:#x}",
+                a.label(),
+                offset,
+                end,
+                (offset / bank + 1) * bank
+            )));
+        }
+    }
+}
+
+// anchor_offset: where an anchored Area goes, or None if it is anchored
+// to an Area that hasn't been placed yet.
+fn anchor_offset(areas: &[Area], a: &Area, opts: &LayoutOpts) -> Result<Option<usize>> {
+    let anchor = a.anchor.as_deref().unwrap();
+    let align = align_of(a, opts)?;
+    if anchor == "top" {
+        let top = opts.flash_size.ok_or_else(|| {
+            LayoutError::Layout(format!(
+                "Area '{}' is anchored to the top, which needs --flash-size",
+                a.label()
+            ))
+        })? as usize;
+        let start = top.checked_sub(a.size).ok_or_else(|| {
+            LayoutError::Layout(format!(
+                "Area '{}' is bigger than the {:#x} byte flash",
+                a.label(),
+                top
+            ))
+        })?;
+        return Ok(Some(start / align * align));
+    }
+    let b = areas
+        .iter()
+        .find(|b| b.name == anchor || b.label() == anchor)
+        .ok_or_else(|| {
+            LayoutError::Layout(format!(
+                "Area '{}' is anchored to '{}', but there is no such area",
+                a.label(),
+                anchor
+            ))
+        })?;
+    Ok(b.offset.map(|offset| round_up(offset + b.size, align)))
+}
+
+// suggest_offsets: a conflict-free offset for every Area, found by
+// packing them densely in DTS order as --auto-pack does, or None if even
+// that fails, e.g. because an Area has an alignment of 0.
+pub fn suggest_offsets(areas: &[Area], opts: &LayoutOpts) -> Option<Vec<(String, usize)>> {
+    let mut packed = areas.to_vec();
+    let opts = LayoutOpts {
+        allow_overlap: false,
+        auto_pack: true,
+        relocate: vec![],
+        ..opts.clone()
+    };
+    compute_layout(&mut packed, &opts).ok()?;
+    Some(
+        packed
+            .into_iter()
+            .map(|a| (a.name, a.offset.unwrap()))
+            .collect(),
+    )
+}
+
+// Summary: what layout_flash wrote, printed by --summary.
+// fill counts the bytes of Areas not covered by a file, gaps the
+// bytes between Areas. With --mirror-at both count the copy too.
+// unresolved holds the label and path of each file or pad-file that was
+// skipped because it names an environment variable that isn't set; it
+// is printed by --list-unresolved-env instead.
+#[derive(Debug, Default, PartialEq)]
+pub struct Summary {
+    pub image_size: usize,
+    pub areas: usize,
+    pub fill: usize,
+    pub gaps: usize,
+    pub unresolved: Vec<(String, String)>,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "image size: {} bytes", self.image_size)?;
+        writeln!(f, "areas: {}", self.areas)?;
+        writeln!(f, "fill: {} bytes", self.fill)?;
+        write!(f, "gaps: {} bytes", self.gaps)
+    }
+}
+
+// layout_flash: lay out the Areas and write the image to a file.
+// If the path is an existing device node, e.g. an SPI flash, the image
+// is written into it instead; see write_device.
+pub fn layout_flash(
+    path: &Path,
+    areas: &mut [Area],
+    opts: &LayoutOpts,
+    stdin: &mut dyn Read,
+) -> Result<Summary> {
+    match fs::metadata(path) {
+        Ok(m) if !m.file_type().is_file() => write_device(path, areas, opts, stdin),
+        _ => {
+            let mut f = fs::OpenOptions::new()
+                .read(opts.verify_write)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            write_image(&mut f, areas, opts, stdin)
+        }
+    }
+}
+
+// layout_flash_compressed: lay out the Areas in memory, then write the
+// compressed image to the path with the compression's extension added.
+pub fn layout_flash_compressed(
+    path: &Path,
+    areas: &mut [Area],
+    opts: &LayoutOpts,
+    stdin: &mut dyn Read,
+    c: Compression,
+) -> Result<Summary> {
+    let mut image = io::Cursor::new(Vec::new());
+    let summary = write_image(&mut image, areas, opts, stdin)?;
+    fs::write(c.path(path), compress(&image.into_inner(), c)?)?;
+    Ok(summary)
+}
+
+// write_device: write the image into an existing file without creating,
+// truncating or extending it. The layout must fit within the file's size,
+// which for a block device is the size of the device.
+fn write_device(
+    path: &Path,
+    areas: &mut [Area],
+    opts: &LayoutOpts,
+    stdin: &mut dyn Read,
+) -> Result<Summary> {
+    let mut f = fs::OpenOptions::new()
+        .read(opts.verify_write)
+        .write(true)
+        .open(path)?;
+    let size = f.seek(SeekFrom::End(0))? as usize;
+    compute_layout(areas, opts)?;
+    let end = areas
+        .iter()
+        .map(|a| a.offset.unwrap() + a.size)
+        .max()
+        .unwrap_or(0);
+    let end = end + opts.mirror_at.unwrap_or(0) as usize;
+    if end > size {
+        return Err(LayoutError::Layout(format!(
+            "The image needs {:#x} bytes, but {} only has {:#x}",
+            end,
+            path.display(),
+            size
+        )));
+    }
+    write_laid_out(&mut f, areas, opts, stdin)
+}
+
+// itemize: what each part of an Area costs, e.g. "file 300 + crc trailer 8".
+fn itemize(items: &[(&str, usize)]) -> String {
+    items
+        .iter()
+        .map(|(what, size)| format!("{} {}", what, size))
+        .collect::<Vec<_>>()
+        .join(" + ")
+}
+
+// write_at: write all the bytes at an offset, wherever the writer was.
+// Every write of the image goes through here.
+fn write_at<W: Write + Seek>(f: &mut W, offset: u64, bytes: &[u8]) -> io::Result<()> {
+    f.seek(SeekFrom::Start(offset))?;
+    f.write_all(bytes)
+}
+
+// The most fill write_fill writes at once.
+const FILL_CHUNK: usize = 64 << 10;
+
+// write_fill: write what Area::filled returns, but a chunk at a time, so
+// that filling a large Area doesn't need a buffer as big as it is. Each
+// chunk but the last holds whole repetitions of the fill pattern.
+fn write_fill<W: Write + Seek>(f: &mut W, offset: u64, a: &Area) -> io::Result<()> {
+    let pattern = match &a.fill_pattern {
+        Some(pattern) => pattern.clone(),
+        None => vec![a.fill.unwrap_or(0xff)],
+    };
+    let len = (FILL_CHUNK / pattern.len()).max(1) * pattern.len();
+    let chunk: Vec<u8> = pattern
+        .iter()
+        .copied()
+        .cycle()
+        .take(len.min(a.size))
+        .collect();
+    let mut written = 0;
+    while written < a.size {
+        let n = (a.size - written).min(chunk.len());
+        write_at(f, offset + written as u64, &chunk[..n])?;
+        written += n;
+    }
+    Ok(())
+}
+
+// Mirrored: a writer that writes everything twice, at its offset and
+// `at` bytes further on.
+struct Mirrored<'a, W> {
+    inner: &'a mut W,
+    at: u64,
+    pos: u64,
+}
+
+impl<W: Write + Seek> Write for Mirrored<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(self.pos))?;
+        let n = self.inner.write(buf)?;
+        write_at(self.inner, self.pos + self.at, &buf[..n])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Read + Seek> Read for Mirrored<'_, W> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.seek(SeekFrom::Start(self.pos))?;
+        let n = self.inner.read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<W: Write + Seek> Seek for Mirrored<'_, W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = match pos {
+            SeekFrom::Start(n) => n,
+            SeekFrom::Current(d) => self.pos.checked_add_signed(d).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "seek before the start")
+            })?,
+            SeekFrom::End(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    "a mirrored image has no end to seek from",
+                ))
+            }
+        };
+        Ok(self.pos)
+    }
+}
+
+// Recorder: a writer that keeps a copy of what is written over some
+// ranges of the image, for the checksum Areas. With --verify-write, what
+// is written over the current part of the image, an Area or the GPT,
+// named for the error, is read back a FILL_CHUNK at a time, and the
+// first byte that differs is kept for verify to report once it is done.
+struct Recorder<'a, W> {
+    inner: &'a mut W,
+    pos: u64,
+    copies: Vec<(u64, Vec<u8>)>,
+    current: Option<String>,
+    mismatch: Option<(u64, u8, u8)>,
+}
+
+impl<W: Read + Write + Seek> Recorder<'_, W> {
+    // verify: report the first byte of the current part, if there is
+    // one, that didn't read back as it was written.
+    fn verify(&mut self) -> Result<()> {
+        let what = match self.current.take() {
+            Some(what) => what,
+            None => return Ok(()),
+        };
+        match self.mismatch.take() {
+            Some((at, got, want)) => Err(LayoutError::Layout(format!(
+                "{} failed to verify: the byte at {:#x} reads back as {:#x}, not {:#x}",
+                what, at, got, want
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    // read_back: read back the bytes just written at start, and keep the
+    // first one that differs, leaving the writer after them.
+    fn read_back(&mut self, start: u64, written: &[u8]) -> io::Result<()> {
+        let mut got = vec![0; written.len().min(FILL_CHUNK)];
+        for (i, want) in written.chunks(FILL_CHUNK).enumerate() {
+            let at = start + (i * FILL_CHUNK) as u64;
+            let got = &mut got[..want.len()];
+            self.inner.seek(SeekFrom::Start(at))?;
+            self.inner.read_exact(got)?;
+            if let Some(j) = got.iter().zip(want).position(|(g, w)| g != w) {
+                self.mismatch = Some((at + j as u64, got[j], want[j]));
+                break;
+            }
+        }
+        self.inner
+            .seek(SeekFrom::Start(start + written.len() as u64))?;
+        Ok(())
+    }
+}
+
+impl<W: Read + Write + Seek> Write for Recorder<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        let (start, end) = (self.pos, self.pos + n as u64);
+        if self.current.is_some() && self.mismatch.is_none() {
+            self.read_back(start, &buf[..n])?;
+        }
+        for (at, copy) in self.copies.iter_mut() {
+            let (from, to) = (start.max(*at), end.min(*at + copy.len() as u64));
+            if from < to {
+                copy[(from - *at) as usize..(to - *at) as usize]
+                    .copy_from_slice(&buf[(from - start) as usize..(to - start) as usize]);
+            }
+        }
+        self.pos = end;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Read + Write + Seek> Seek for Recorder<'_, W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.pos = self.inner.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+// write_mirrored: write the image, and a copy of it at `at`. The copy
+// must start after the image, the seed image included, and end within
+// the flash if its size is known.
+fn write_mirrored<W: Read + Write + Seek>(
+    f: &mut W,
+    areas: &mut [Area],
+    opts: &LayoutOpts,
+    stdin: &mut dyn Read,
+    at: u64,
+) -> Result<Summary> {
+    let seed_size = match &opts.seed_image {
+        Some(seed) => fs::metadata(seed)?.len(),
+        None => 0,
+    };
+    let size = areas
+        .iter()
+        .map(|a| (a.offset.unwrap() + a.size) as u64)
+        .max()
+        .unwrap_or(0)
+        .max(seed_size);
+    if at < size {
+        return Err(LayoutError::Layout(format!(
+            "The mirror at {:#x} would overlap the image, which ends at {:#x}",
+            at, size
+        )));
+    }
+    if let Some(flash_size) = opts.flash_size {
+        if at + size > flash_size {
+            return Err(LayoutError::Layout(format!(
+                "The mirror at {:#x} ends at {:#x}, past the end of the {:#x} byte flash",
+                at,
+                at + size,
+                flash_size
+            )));
+        }
+    }
+    let mut mirrored = Mirrored {
+        inner: f,
+        at,
+        pos: 0,
+    };
+    let mut summary = write_areas(&mut mirrored, areas, opts, stdin)?;
+    summary.fill *= 2;
+    summary.gaps = summary.gaps * 2 + (at as usize - summary.image_size);
+    summary.image_size += at as usize;
+    Ok(summary)
+}
+
+// pack: lay out the Areas and return the image, without touching the
+// filesystem beyond reading the Areas' files. This is the in-memory
+// counterpart of layout_flash, e.g. for use from a build.rs.
+pub fn pack(areas: &mut [Area], opts: &LayoutOpts) -> Result<Vec<u8>> {
+    let mut image = io::Cursor::new(Vec::new());
+    write_image(&mut image, areas, opts, &mut io::stdin())?;
+    Ok(image.into_inner())
+}
+
+// write_image writes the Areas ordered by their `index` property, then by
+// offset, so that when overlaps are allowed the later Area's bytes win.
+// An Area without an `index` uses its position in the DTS.
+// At most one Area may read its contents from stdin.
+// With a seed image, the seed is written first and the Areas over it.
+// The layout is computed here, once; nothing the image is written by
+// computes it again.
+fn write_image<W: Read + Write + Seek>(
+    f: &mut W,
+    areas: &mut [Area],
+    opts: &LayoutOpts,
+    stdin: &mut dyn Read,
+) -> Result<Summary> {
+    compute_layout(areas, opts)?;
+    write_laid_out(f, areas, opts, stdin)
+}
+
+// write_laid_out: write_image for Areas compute_layout has placed.
+fn write_laid_out<W: Read + Write + Seek>(
+    f: &mut W,
+    areas: &mut [Area],
+    opts: &LayoutOpts,
+    stdin: &mut dyn Read,
+) -> Result<Summary> {
+    match opts.mirror_at {
+        Some(at) => write_mirrored(f, areas, opts, stdin, at),
+        None => write_areas(f, areas, opts, stdin),
+    }
+}
+
+fn write_areas<W: Read + Write + Seek>(
+    f: &mut W,
+    areas: &mut [Area],
+    opts: &LayoutOpts,
+    stdin: &mut dyn Read,
+) -> Result<Summary> {
+    let mut stdin_areas = areas.iter().filter(|a| a.reads_stdin());
+    if let (Some(first), Some(second)) = (stdin_areas.next(), stdin_areas.next()) {
+        return Err(LayoutError::Layout(format!(
+            "Only one area can read from stdin, but both '{}' and '{}' do",
+            first.name, second.name
+        )));
+    }
+    let mut order: Vec<(usize, &Area)> = areas
+        .iter()
+        .enumerate()
+        .map(|(i, a)| (a.index.unwrap_or(i), a))
+        .collect();
+    order.sort_by_key(|(index, a)| (*index, a.offset));
+    // The checksum Areas are written last, over the fill, from what was
+    // written over the Areas they cover.
+    let tables: Vec<(&Area, Vec<&Area>)> = areas
+        .iter()
+        .filter(|a| a.compatible.as_deref() == Some(checksums::COMPATIBLE))
+        .map(|a| Ok((a, covered(a, areas)?)))
+        .collect::<Result<_>>()?;
+    let mut recorder = Recorder {
+        inner: f,
+        pos: 0,
+        copies: tables
+            .iter()
+            .flat_map(|(_, covered)| covered)
+            .map(|c| (c.offset.unwrap() as u64, vec![0; c.size]))
+            .collect(),
+        current: None,
+        mismatch: None,
+    };
+    let f = &mut recorder;
+    let mut seed_size = 0;
+    if let Some(seed) = &opts.seed_image {
+        let seed = read_file(&seed.to_string_lossy())?;
+        debug!("seeded the image with 0x{:x} bytes", seed.len());
+        seed_size = seed.len();
+        write_at(f, 0, &seed)?;
+    }
+    let mut summary = Summary {
+        areas: areas.len(),
+        ..Default::default()
+    };
+    for (_, a) in order {
+        f.verify()?;
+        // compute_layout has resolved every offset.
+        let offset = a.offset.unwrap();
+        info!(
+            "Area {:?}: @0x{:x}, size 0x{:x}, source {}",
+            a.name,
+            offset,
+            a.size,
+            a.file.as_deref().unwrap_or("fill")
+        );
+        let reservations = a.reservations();
+        let reserved: usize = reservations.iter().map(|(_, size)| size).sum();
+        if reserved > a.size {
+            return Err(LayoutError::Layout(format!(
+                "Area '{}' is too small for its {} reserved bytes, {}",
+                a.name,
+                reserved,
+                itemize(&reservations)
+            )));
+        }
+        if opts.verify_write {
+            f.current = Some(format!("Area '{}'", a.label()));
+        }
+        // First fill with the Area's fill-pattern or fill byte.
+        summary.fill += a.size;
+        write_fill(f, offset as u64, a)?;
+
+        // If a file is specified, or the Area is generated, write its data.
+        let (path, mut data) = if a.compatible.as_deref() == Some(build_info::COMPATIBLE) {
+            (
+                "<build-info>".to_string(),
+                build_info(opts.git_hash.as_deref(), opts.reproducible)?,
+            )
+        } else if a.compatible.as_deref() == Some(image_meta::COMPATIBLE) {
+            (
+                "<image-meta>".to_string(),
+                image_meta(opts.fdt_hash.as_ref(), areas.len(), opts.reproducible)?,
+            )
+        } else if a.compatible.as_deref() == Some(area_index::COMPATIBLE) {
+            ("<area-index>".to_string(), area_index(a, areas)?)
+        } else if a.compatible.as_deref() == Some(fmap::COMPATIBLE) {
+            let base = opts.base_address.unwrap_or(0);
+            let data = if opts.reproducible {
+                let mut by_offset = areas.to_vec();
+                by_offset.sort_by_key(|a| a.offset);
+                fmap(&by_offset, base)?
+            } else {
+                fmap(areas, base)?
+            };
+            ("<fmap>".to_string(), data)
+        } else if let Some(path) = &a.file {
+            if a.reads_stdin() {
+                let mut data = Vec::new();
+                stdin.read_to_end(&mut data)?;
+                ("<stdin>".to_string(), data)
+            } else {
+                // If the path is an unused environment variable, skip it.
+                let path = match expand_path(path) {
+                    Some(path) => path,
+                    None => {
+                        debug!("<{}> {} is not set, leaving the area filled", a.name, path);
+                        summary
+                            .unresolved
+                            .push((a.label().to_string(), path.clone()));
+                        continue;
+                    }
+                };
+                let data = read_file(&path)?;
+                (path, data)
+            }
+        } else {
+            (String::new(), Vec::new())
+        };
+        // With `truncate-input` only the leading bytes that fit are written.
+        if data.len() > a.capacity() && a.truncate_input {
+            debug!(
+                "<{}> truncated {} from 0x{:x} to 0x{:x} bytes",
+                a.name,
+                path,
+                data.len(),
+                a.capacity()
+            );
+            data.truncate(a.capacity());
+        }
+        if data.len() > a.capacity() {
+            let mut msg = format!(
+                "File {} is too big to fit into the flash area, file size: {}, area size: {}",
+                path,
+                data.len(),
+                a.capacity()
+            );
+            if !reservations.is_empty() {
+                let mut items = vec![("file", data.len())];
+                items.extend(&reservations);
+                msg += &format!(
+                    "; it needs {} bytes, {}, of the area's {}",
+                    data.len() + reserved,
+                    itemize(&items),
+                    a.size
+                );
+            }
+            if let Some(hint) = sniff::hint(&data) {
+                msg += "; ";
+                msg += hint;
+            }
+            return Err(LayoutError::Layout(msg));
+        }
+        // The length prefix goes in front, and counts as contents from here on.
+        let (prefix, prefix_size) = match a.length_prefix {
+            Some(prefix) => (prefix.encode(data.len()), LengthPrefix::SIZE),
+            None => (None, 0),
+        };
+        if prefix_size > 0 {
+            let prefix = prefix.ok_or_else(|| {
+                LayoutError::Layout(format!(
+                    "Area '{}': the length {:#x} of {} doesn't fit in its length prefix",
+                    a.label(),
+                    data.len(),
+                    path
+                ))
+            })?;
+            data.splice(0..0, prefix);
+        }
+        if !data.is_empty() {
+            debug!("<{}> wrote 0x{:x} bytes from {}", a.name, data.len(), path);
+            summary.fill -= data.len();
+            write_at(f, offset as u64, &data)?;
+        }
+
+        if a.crc_trailer {
+            summary.fill -= TRAILER_SIZE;
+            write_at(f, (offset + a.size - TRAILER_SIZE) as u64, &trailer(&data))?;
+        }
+
+        // If a pad file is specified, repeat it over the rest of the Area.
+        if let Some(path) = &a.pad_file {
+            let path = match expand_path(path) {
+                Some(path) => path,
+                None => {
+                    debug!(
+                        "<{}> {} is not set, leaving the padding filled",
+                        a.name, path
+                    );
+                    summary
+                        .unresolved
+                        .push((a.label().to_string(), path.clone()));
+                    continue;
+                }
+            };
+            let pad = read_file(&path)?;
+            if pad.is_empty() {
+                continue;
+            }
+            let tail: Vec<u8> = pad
+                .iter()
+                .copied()
+                .cycle()
+                .take(a.capacity() + prefix_size - data.len())
+                .collect();
+            debug!("<{}> padded 0x{:x} bytes from {}", a.name, tail.len(), path);
+            summary.fill -= tail.len();
+            write_at(f, (offset + data.len()) as u64, &tail)?;
+        }
+    }
+
+    f.verify()?;
+    let mut copies = std::mem::take(&mut f.copies).into_iter();
+    for (a, covered) in &tables {
+        let covered: Vec<(&Area, Vec<u8>)> = covered
+            .iter()
+            .map(|c| (*c, copies.next().unwrap().1))
+            .collect();
+        let table = checksums(a, &covered)?;
+        debug!(
+            "<{}> wrote the checksums of {} areas",
+            a.name,
+            covered.len()
+        );
+        if opts.verify_write {
+            f.current = Some(format!("Area '{}'", a.label()));
+        }
+        write_at(f, a.offset.unwrap() as u64, &table)?;
+        f.verify()?;
+        summary.fill -= table.len();
+    }
+
+    let mut sorted: Vec<&Area> = areas.iter().collect();
+    sorted.sort_by_key(|a| a.offset);
+    for a in sorted {
+        let offset = a.offset.unwrap();
+        summary.gaps += offset.saturating_sub(summary.image_size);
+        summary.image_size = summary.image_size.max(offset + a.size);
+    }
+    summary.image_size = summary.image_size.max(seed_size);
+
+    if opts.gpt {
+        let disk_size = opts
+            .flash_size
+            .ok_or_else(|| LayoutError::Input("--gpt needs --flash-size".to_string()))?;
+        if opts.verify_write {
+            f.current = Some("The GPT".to_string());
+        }
+        for (offset, table) in gpt(areas, disk_size, opts.sector_size.unwrap_or(512))? {
+            write_at(f, offset, &table)?;
+        }
+        f.verify()?;
+        summary.image_size = summary.image_size.max(disk_size as usize);
+    }
+    Ok(summary)
+}
+
+// AreaDiff: how an Area differs between two images.
+#[derive(Debug, PartialEq)]
+pub struct AreaDiff {
+    pub name: String,
+    // The first differing offset, None if the Area is identical.
+    pub first: Option<usize>,
+    // The number of differing bytes.
+    pub count: usize,
+}
+
+// diff_areas: compare two images Area by Area. Bytes beyond the end of
+// the shorter image count as differing.
+pub fn diff_areas(areas: &[Area], old: &[u8], new: &[u8]) -> Vec<AreaDiff> {
+    areas
+        .iter()
+        .map(|a| {
+            let offset = a.offset.unwrap();
+            let differing: Vec<usize> = (offset..offset + a.size)
+                .filter(|i| old.get(*i) != new.get(*i))
+                .collect();
+            AreaDiff {
+                name: a.label().to_string(),
+                first: differing.first().copied(),
+                count: differing.len(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{area, temp_path};
+    use crate::trailer::check_trailer;
+
+    #[test]
+    fn zero_size_area() {
+        let mut areas = vec![
+            area("area@0", Some(0), 0x100),
+            area("area@1", None, 0),
+            area("area@2", None, 0x100),
+        ];
+        compute_layout(&mut areas, &LayoutOpts::default()).unwrap();
+        assert_eq!(areas[1].offset, Some(0x100));
+        assert_eq!(areas[2].offset, Some(0x100));
+
+        let mut areas = vec![
+            area("area@0", Some(0), 0x100),
+            area("area@1", Some(0x80), 0),
+        ];
+        assert!(compute_layout(&mut areas, &LayoutOpts::default()).is_err());
+    }
+
+    #[test]
+    fn allow_overlap_later_area_wins() {
+        let low = temp_path("overlap-low");
+        let high = temp_path("overlap-high");
+        let out = temp_path("overlap-out");
+        fs::write(&low, [0x11; 0x10]).unwrap();
+        fs::write(&high, [0x22; 0x8]).unwrap();
+        // The patch is declared first but has the higher index.
+        let mut patch = area("patch", Some(0x4), 0x8);
+        patch.file = Some(high.to_string_lossy().to_string());
+        patch.index = Some(1);
+        let mut default = area("default", Some(0), 0x10);
+        default.file = Some(low.to_string_lossy().to_string());
+        default.index = Some(0);
+        let mut areas = vec![patch, default];
+
+        assert!(compute_layout(&mut areas, &LayoutOpts::default()).is_err());
+        layout_flash(
+            &out,
+            &mut areas,
+            &LayoutOpts {
+                allow_overlap: true,
+                ..Default::default()
+            },
+            &mut io::empty(),
+        )
+        .unwrap();
+        let data = fs::read(&out).unwrap();
+        for f in [low, high, out] {
+            fs::remove_file(f).unwrap();
+        }
+        assert_eq!(&data[..0x4], &[0x11; 0x4]);
+        assert_eq!(&data[0x4..0xc], &[0x22; 0x8]);
+        assert_eq!(&data[0xc..], &[0x11; 0x4]);
+    }
+
+    #[test]
+    fn area_from_stdin() {
+        let out = temp_path("stdin-out");
+        let mut piped = area("piped", None, 0x8);
+        piped.file = Some("-".to_string());
+        let mut areas = vec![area("area@0", Some(0), 0x4), piped];
+        layout_flash(
+            &out,
+            &mut areas,
+            &LayoutOpts::default(),
+            &mut &[0x5a; 4][..],
+        )
+        .unwrap();
+        let data = fs::read(&out).unwrap();
+        fs::remove_file(&out).unwrap();
+        assert_eq!(
+            data,
+            [0xff, 0xff, 0xff, 0xff, 0x5a, 0x5a, 0x5a, 0x5a, 0xff, 0xff, 0xff, 0xff]
+        );
+
+        let mut again = area("again", None, 0x8);
+        again.file = Some("$(STDIN)".to_string());
+        areas.push(again);
+        assert!(layout_flash(&out, &mut areas, &LayoutOpts::default(), &mut io::empty()).is_err());
+    }
+
+    #[test]
+    fn summary() {
+        let file = temp_path("summary-file");
+        let out = temp_path("summary-out");
+        fs::write(&file, [0x33; 0x30]).unwrap();
+        let mut used = area("used", None, 0x100);
+        used.file = Some(file.to_string_lossy().to_string());
+        let mut areas = vec![used, area("empty", Some(0x180), 0x80)];
+        let summary =
+            layout_flash(&out, &mut areas, &LayoutOpts::default(), &mut io::empty()).unwrap();
+        for f in [file, out] {
+            fs::remove_file(f).unwrap();
+        }
+        assert_eq!(
+            summary,
+            Summary {
+                image_size: 0x200,
+                areas: 2,
+                fill: 0x150,
+                gaps: 0x80,
+                unresolved: vec![],
+            }
+        );
+        assert_eq!(
+            summary.to_string(),
+            "image size: 512 bytes\nareas: 2\nfill: 336 bytes\ngaps: 128 bytes"
+        );
+    }
+
+    #[test]
+    fn unresolved_env() {
+        let pad = temp_path("unresolved-pad");
+        fs::write(&pad, [0x5a]).unwrap();
+        let mut skipped = area("area@0", None, 0x10);
+        skipped.file = Some("$(LAYOUTFLASH_TEST_UNSET_FILE)".to_string());
+        let mut padded = area("area@1", None, 0x10);
+        padded.description = Some("padded".to_string());
+        padded.file = Some(pad.to_str().unwrap().to_string());
+        padded.pad_file = Some("$(LAYOUTFLASH_TEST_UNSET_PAD)".to_string());
+        let mut image = io::Cursor::new(Vec::new());
+        let summary = write_image(
+            &mut image,
+            &mut [skipped, padded, area("area@2", None, 0x10)],
+            &LayoutOpts::default(),
+            &mut io::empty(),
+        )
+        .unwrap();
+        fs::remove_file(pad).unwrap();
+        assert_eq!(
+            summary.unresolved,
+            [
+                (
+                    "area@0".to_string(),
+                    "$(LAYOUTFLASH_TEST_UNSET_FILE)".to_string()
+                ),
+                (
+                    "padded".to_string(),
+                    "$(LAYOUTFLASH_TEST_UNSET_PAD)".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn align_all_and_per_area_align() {
+        let opts = LayoutOpts {
+            align_all: Some(0x100),
+            ..Default::default()
+        };
+        let mut aligned = area("aligned", None, 0x10);
+        aligned.align = Some(0x1000);
+        let mut areas = vec![
+            area("area@0", None, 0x10),
+            area("area@1", None, 0x10),
+            aligned,
+            area("area@3", Some(0x1100), 0x10),
+        ];
+        compute_layout(&mut areas, &opts).unwrap();
+        let offsets: Vec<_> = areas.iter().map(|a| a.offset.unwrap()).collect();
+        assert_eq!(offsets, [0, 0x100, 0x1000, 0x1100]);
+
+        let mut areas = vec![
+            area("area@0", None, 0x10),
+            area("area@1", Some(0x180), 0x10),
+        ];
+        assert!(compute_layout(&mut areas, &opts).is_err());
+    }
+
+    #[test]
+    fn diff_one_area() {
+        let mut areas = vec![
+            area("area@0", None, 0x10),
+            area("area@1", None, 0x10),
+            area("area@2", None, 0x10),
+        ];
+        compute_layout(&mut areas, &LayoutOpts::default()).unwrap();
+        let old = vec![0xffu8; 0x30];
+        let mut new = old.clone();
+        new[0x14] = 0;
+        new[0x18] = 0;
+        let diffs = diff_areas(&areas, &old, &new);
+        assert_eq!(diffs[0].first, None);
+        assert_eq!(
+            diffs[1],
+            AreaDiff {
+                name: "area@1".to_string(),
+                first: Some(0x14),
+                count: 2,
+            }
+        );
+        assert_eq!(diffs[2].first, None);
+    }
+
+    #[test]
+    fn overlap_suggests_offset_and_auto_pack() {
+        let mut areas = vec![
+            area("area@0", Some(0), 0x100),
+            area("area@1", Some(0x80), 0x100),
+            area("area@2", None, 0x10),
+        ];
+        let opts = LayoutOpts {
+            align_all: Some(0x40),
+            ..Default::default()
+        };
+        let err = compute_layout(&mut areas, &opts).unwrap_err();
+        assert!(
+            err.to_string().contains("it must start at 0x100 or later"),
+            "{}",
+            err
+        );
+
+        let opts = LayoutOpts {
+            auto_pack: true,
+            ..opts
+        };
+        compute_layout(&mut areas, &opts).unwrap();
+        let offsets: Vec<_> = areas.iter().map(|a| a.offset.unwrap()).collect();
+        assert_eq!(offsets, [0, 0x100, 0x200]);
+    }
+
+    #[test]
+    fn pack_matches_layout_flash() {
+        let file = temp_path("pack-file");
+        let out = temp_path("pack-out");
+        fs::write(&file, [0x44; 0x20]).unwrap();
+        let areas = || {
+            let mut used = area("used", Some(0x10), 0x40);
+            used.file = Some(file.to_string_lossy().to_string());
+            vec![
+                area("area@0", Some(0), 0x10),
+                used,
+                area("area@2", Some(0x80), 0x10),
+            ]
+        };
+        let opts = LayoutOpts::default();
+        layout_flash(&out, &mut areas(), &opts, &mut io::empty()).unwrap();
+        let packed = pack(&mut areas(), &opts).unwrap();
+        let written = fs::read(&out).unwrap();
+        for f in [file, out] {
+            fs::remove_file(f).unwrap();
+        }
+        assert_eq!(packed, written);
+    }
+
+    #[test]
+    fn pad_file_repeats_and_truncates() {
+        let file = temp_path("pad-main");
+        let pad = temp_path("pad-pattern");
+        fs::write(&file, [1, 2]).unwrap();
+        fs::write(&pad, [0xa, 0xb, 0xc]).unwrap();
+        let mut main = area("area@0", Some(0), 8);
+        main.file = Some(file.to_str().unwrap().to_string());
+        main.pad_file = Some(pad.to_str().unwrap().to_string());
+        let mut short = area("area@8", Some(8), 2);
+        short.pad_file = main.pad_file.clone();
+        let image = pack(&mut [main, short], &LayoutOpts::default()).unwrap();
+        fs::remove_file(file).unwrap();
+        fs::remove_file(pad).unwrap();
+        assert_eq!(image, [1, 2, 0xa, 0xb, 0xc, 0xa, 0xb, 0xc, 0xa, 0xb]);
+    }
+
+    #[test]
+    fn crc_trailer() {
+        let file = temp_path("crc-trailer");
+        fs::write(&file, b"payload").unwrap();
+        let mut a = area("area@0", Some(0), 0x20);
+        a.file = Some(file.to_str().unwrap().to_string());
+        a.crc_trailer = true;
+        let image = pack(&mut [a], &LayoutOpts::default()).unwrap();
+        assert_eq!(check_trailer(&image), Some(&b"payload"[..]));
+        assert_eq!(image[7..0x18], [0xff; 0x11]);
+
+        // The trailer comes out of the usable size.
+        fs::write(&file, [0; 0x19]).unwrap();
+        let mut a = area("area@0", Some(0), 0x20);
+        a.file = Some(file.to_str().unwrap().to_string());
+        a.crc_trailer = true;
+        assert!(pack(&mut [a], &LayoutOpts::default()).is_err());
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn write_device_does_not_truncate() {
+        let dev = temp_path("device");
+        fs::write(&dev, [0x55; 0x40]).unwrap();
+        let mut areas = vec![area("area@0", Some(0x10), 0x10)];
+        write_device(&dev, &mut areas, &LayoutOpts::default(), &mut io::empty()).unwrap();
+        let data = fs::read(&dev).unwrap();
+        assert_eq!(data.len(), 0x40);
+        assert_eq!(data[..0x10], [0x55; 0x10]);
+        assert_eq!(data[0x10..0x20], [0xff; 0x10]);
+        assert_eq!(data[0x20..], [0x55; 0x20]);
+
+        // Nor extend it.
+        let mut areas = vec![area("area@0", Some(0x30), 0x20)];
+        assert!(write_device(&dev, &mut areas, &LayoutOpts::default(), &mut io::empty()).is_err());
+        assert_eq!(fs::read(&dev).unwrap().len(), 0x40);
+        fs::remove_file(dev).unwrap();
+    }
+
+    #[test]
+    fn compressed_output_matches_pack() {
+        let areas = || {
+            vec![
+                area("area@0", Some(0), 0x100),
+                area("area@1", Some(0x200), 0x80),
+            ]
+        };
+        let raw = pack(&mut areas(), &LayoutOpts::default()).unwrap();
+        let out = temp_path("compressed.bin");
+        layout_flash_compressed(
+            &out,
+            &mut areas(),
+            &LayoutOpts::default(),
+            &mut io::empty(),
+            Compression::Zstd,
+        )
+        .unwrap();
+        let path = Compression::Zstd.path(&out);
+        let image = zstd::decode_all(&fs::read(&path).unwrap()[..]).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(image, raw);
+    }
+
+    #[test]
+    fn suggested_offsets_lay_out() {
+        let mut areas = vec![
+            area("area@0", Some(0), 0x100),
+            area("area@1", Some(0x80), 0x100),
+            area("area@2", None, 0x10),
+        ];
+        areas[2].align = Some(0x1000);
+        assert!(compute_layout(&mut areas.clone(), &LayoutOpts::default()).is_err());
+
+        let suggestions = suggest_offsets(&areas, &LayoutOpts::default()).unwrap();
+        assert_eq!(
+            suggestions,
+            [
+                ("area@0".to_string(), 0),
+                ("area@1".to_string(), 0x100),
+                ("area@2".to_string(), 0x1000)
+            ]
+        );
+        for (a, (_, offset)) in areas.iter_mut().zip(suggestions) {
+            a.offset = Some(offset);
+        }
+        compute_layout(&mut areas, &LayoutOpts::default()).unwrap();
+    }
+
+    #[test]
+    fn anchors() {
+        let mut areas = vec![
+            area("area@0", Some(0), 0x100),
+            area("area@1", None, 0x200),
+            area("area@2", None, 0x100),
+            area("area@3", None, 0x10),
+        ];
+        areas[0].description = Some("Boot Blob".to_string());
+        areas[1].anchor = Some("top".to_string());
+        areas[1].align = Some(0x1000);
+        areas[2].anchor = Some("Boot Blob".to_string());
+        // Anchored to an anchored area.
+        areas[3].anchor = Some("area@2".to_string());
+        let opts = LayoutOpts {
+            flash_size: Some(0x10000),
+            ..Default::default()
+        };
+        compute_layout(&mut areas, &opts).unwrap();
+        let offsets: Vec<_> = areas.iter().map(|a| a.offset.unwrap()).collect();
+        assert_eq!(offsets, [0, 0xf000, 0x100, 0x200]);
+        // Laying out again gives the same offsets.
+        compute_layout(&mut areas, &opts).unwrap();
+        assert_eq!(areas[3].offset, Some(0x200));
+
+        // Top needs a flash size.
+        assert!(compute_layout(&mut areas, &LayoutOpts::default()).is_err());
+
+        areas[2].anchor = Some("area@3".to_string());
+        let err = compute_layout(&mut areas, &opts).unwrap_err();
+        assert!(err.to_string().contains("form a cycle"), "{}", err);
+
+        // Anchored areas can still overlap others.
+        areas[2].anchor = Some("Boot Blob".to_string());
+        areas[3].anchor = None;
+        areas[3].offset = Some(0x180);
+        let err = compute_layout(&mut areas, &opts).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("anchored at 0x100, inside area 'area@3'"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn too_big_elf_hint() {
+        let file = temp_path("too-big.elf");
+        fs::write(&file, b"\x7fELF\x02\x01\x01\0\0\0\0\0\0\0\0\0").unwrap();
+        let mut a = area("area@0", Some(0), 8);
+        a.file = Some(file.to_str().unwrap().to_string());
+        let err = pack(&mut [a], &LayoutOpts::default()).unwrap_err();
+        fs::remove_file(file).unwrap();
+        assert!(err.to_string().contains("appears to be an ELF"), "{}", err);
+    }
+
+    #[test]
+    fn area_size_limits() {
+        let mut areas = vec![area("area@0", None, 0x1000), area("area@1", None, 0x100000)];
+        let opts = LayoutOpts {
+            max_area_size: Some(0x10000),
+            ..Default::default()
+        };
+        assert_eq!(
+            oversized(&areas, &opts),
+            ["Area 'area@1' is 0x100000 bytes, more than --max-area-size 0x10000"]
+        );
+        // Only a warning.
+        compute_layout(&mut areas, &opts).unwrap();
+
+        let opts = LayoutOpts {
+            flash_size: Some(0x80000),
+            ..Default::default()
+        };
+        let err = compute_layout(&mut areas, &opts).unwrap_err();
+        assert!(
+            err.messages()[0].contains("bigger than the whole 0x80000 byte flash"),
+            "{}",
+            err
+        );
+
+        // Placed Areas must end within the flash, as auto-placed ones do.
+        let opts = LayoutOpts {
+            flash_size: Some(0x100),
+            ..Default::default()
+        };
+        let mut areas = vec![area("area@0", Some(0xf0), 0x20)];
+        let err = compute_layout(&mut areas, &opts).unwrap_err();
+        assert!(err.to_string().contains("runs past the end"), "{}", err);
+        let mut areas = vec![area("area@0", Some(usize::MAX), 0x20)];
+        assert!(compute_layout(&mut areas, &opts).is_err());
+    }
+
+    #[test]
+    fn auto_areas_skip_reservations() {
+        let mut areas = vec![
+            area("area@0", None, 0x100),
+            area("area@1", None, 0x100),
+            area("area@2", Some(0x180), 0x100),
+            area("area@3", None, 0x10),
+        ];
+        areas[1].align = Some(0x40);
+        let opts = LayoutOpts::default();
+        compute_layout(&mut areas, &opts).unwrap();
+        let offsets: Vec<_> = areas.iter().map(|a| a.offset.unwrap()).collect();
+        // area@1 doesn't fit before area@2, so goes after it.
+        assert_eq!(offsets, [0, 0x280, 0x180, 0x380]);
+        // Laying out the placed Areas again changes nothing.
+        compute_layout(&mut areas, &opts).unwrap();
+        let again: Vec<_> = areas.iter().map(|a| a.offset.unwrap()).collect();
+        assert_eq!(again, offsets);
+
+        // An Area that fits in the gap stays in it.
+        let mut areas = vec![
+            area("area@0", None, 0x80),
+            area("area@1", Some(0x100), 0x100),
+        ];
+        compute_layout(&mut areas, &opts).unwrap();
+        assert_eq!(areas[0].offset, Some(0));
+
+        // And one that fits nowhere in the flash is an error.
+        let mut areas = vec![
+            area("area@0", None, 0x200),
+            area("area@1", Some(0x100), 0x100),
+        ];
+        let opts = LayoutOpts {
+            flash_size: Some(0x300),
+            ..Default::default()
+        };
+        let err = compute_layout(&mut areas, &opts).unwrap_err();
+        assert!(err.to_string().contains("no gap"), "{}", err);
+    }
+
+    #[test]
+    fn page_size() {
+        let areas = || {
+            let mut areas = vec![area("area@0", Some(0), 0x80), area("area@1", None, 0x80)];
+            areas[1].align = Some(0x40);
+            areas
+        };
+        let opts = LayoutOpts {
+            page_size: Some(0x100),
+            ..Default::default()
+        };
+        // area@1 is aligned, but starts mid-page: only a warning...
+        compute_layout(&mut areas(), &opts).unwrap();
+        // ...unless strict.
+        let strict = LayoutOpts {
+            strict: true,
+            ..opts.clone()
+        };
+        let err = compute_layout(&mut areas(), &strict).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area 'area@1' starts at 0x80, which is not on a 0x100 byte page"
+        );
+
+        // An alignment violation is an error either way, and its own.
+        let mut misaligned = areas();
+        misaligned[1].offset = Some(0xa0);
+        let err = compute_layout(&mut misaligned, &opts).unwrap_err();
+        assert!(err.to_string().contains("not aligned to 0x40"), "{}", err);
+    }
+
+    #[test]
+    fn relocate_moves_the_rest() {
+        let areas = || {
+            let mut areas = vec![
+                area("area@0", Some(0), 0x1000),
+                area("area@1", None, 0x1000),
+                area("area@2", Some(0x3000), 0x1000),
+            ];
+            areas[1].description = Some("ramstage".to_string());
+            areas[1].align = Some(0x1000);
+            areas
+        };
+        let opts = LayoutOpts {
+            flash_size: Some(0x8000),
+            relocate: vec![parse_relocation("ramstage=+0x1000").unwrap()],
+            ..Default::default()
+        };
+        let mut moved = areas();
+        compute_layout(&mut moved, &opts).unwrap();
+        let offsets: Vec<_> = moved.iter().map(|a| a.offset.unwrap()).collect();
+        assert_eq!(offsets, [0, 0x2000, 0x4000]);
+
+        // The moved layout is checked again.
+        let misaligned = LayoutOpts {
+            relocate: vec![parse_relocation("ramstage=+0x800").unwrap()],
+            ..opts.clone()
+        };
+        let err = compute_layout(&mut areas(), &misaligned).unwrap_err();
+        assert!(err.to_string().contains("not aligned to 0x1000"), "{}", err);
+        let too_far = LayoutOpts {
+            relocate: vec![parse_relocation("area@2=+4K").unwrap()],
+            ..opts.clone()
+        };
+        let squeezed = |mut areas: Vec<Area>| {
+            areas.push(area("area@3", None, 0x1000));
+            areas[3].anchor = Some("top".to_string());
+            areas
+        };
+        let err = compute_layout(&mut squeezed(areas()), &too_far).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area 'area@3' is anchored to the top, so --relocate area@2 can't move it"
+        );
+        let past_end = LayoutOpts {
+            relocate: vec![parse_relocation("area@2=+0x4800").unwrap()],
+            ..opts
+        };
+        let err = compute_layout(&mut areas(), &past_end).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area 'area@2' at 0x7800 is 0x1000 bytes, and runs past the end of the 0x8000 byte flash"
+        );
+
+        assert_eq!(parse_relocation("a=-0x10"), Ok(("a".to_string(), -0x10)));
+        for bad in ["a", "a=0x10", "a=+ten"] {
+            assert!(parse_relocation(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn bank_size() {
+        let opts = LayoutOpts {
+            bank_size: Some(0x1000),
+            ..Default::default()
+        };
+        // Both fill bank 0 exactly, or sit inside bank 1.
+        let mut areas = vec![area("area@0", Some(0), 0x1000), area("area@1", None, 0x800)];
+        compute_layout(&mut areas, &opts).unwrap();
+
+        // area@1 starts on a bank, but is too big to stay in it.
+        let mut areas = vec![
+            area("area@0", Some(0), 0x1000),
+            area("area@1", None, 0x1800),
+        ];
+        areas[1].align = Some(0x1000);
+        let err = compute_layout(&mut areas, &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area 'area@1' at 0x1000..0x2800 crosses the bank boundary at 0x2000"
+        );
+    }
+
+    #[test]
+    fn every_error() {
+        let mut areas = vec![
+            area("area@0", Some(0), 0x100),
+            area("area@1", Some(0x80), 0x100),
+            area("area@2", Some(0x301), 0x10),
+            area("area@3", None, 0x10),
+        ];
+        areas[2].align = Some(0x100);
+        areas[3].anchor = Some("area@9".to_string());
+        let err = compute_layout(&mut areas, &LayoutOpts::default()).unwrap_err();
+        let messages = err.messages();
+        assert_eq!(messages.len(), 3, "{:?}", messages);
+        assert!(messages[0].contains("'area@1' starts at 128"));
+        assert!(messages[1].contains("'area@2' starts at 0x301"));
+        assert!(messages[2].contains("anchored to 'area@9'"));
+        // The Areas are still placed as best they can be.
+        assert_eq!(areas[2].offset, Some(0x301));
+        assert_eq!(areas[3].offset, None);
+    }
+
+    #[test]
+    fn reservations_itemized() {
+        let file = temp_path("reservations");
+        fs::write(&file, [1; 0x100]).unwrap();
+        let mut a = area("area@0", Some(0), 0x180);
+        a.file = Some(file.to_str().unwrap().to_string());
+        a.crc_trailer = true;
+        a.signature_size = Some(0x80);
+        let err = pack(&mut [a.clone()], &LayoutOpts::default()).unwrap_err();
+        assert!(
+            err.to_string().contains(
+                "it needs 392 bytes, file 256 + signature 128 + crc trailer 8, of the area's 384"
+            ),
+            "{}",
+            err
+        );
+
+        // The signature sits before the trailer, left filled.
+        a.size = 0x188;
+        let image = pack(&mut [a], &LayoutOpts::default()).unwrap();
+        fs::remove_file(file).unwrap();
+        assert_eq!(image[0x100..0x180], [0xff; 0x80]);
+        assert_eq!(image[0x180..0x184], 0x100u32.to_le_bytes());
+    }
+
+    #[test]
+    fn length_prefix() {
+        let file = temp_path("length-prefixed");
+        fs::write(&file, [1, 2, 3]).unwrap();
+        let mut a = area("area@0", Some(0), 8);
+        a.file = Some(file.to_str().unwrap().to_string());
+        a.length_prefix = Some(LengthPrefix::U32Le);
+        let image = pack(&mut [a.clone()], &LayoutOpts::default()).unwrap();
+        assert_eq!(image, [3, 0, 0, 0, 1, 2, 3, 0xff]);
+        a.length_prefix = Some(LengthPrefix::U32Be);
+        let image = pack(&mut [a.clone()], &LayoutOpts::default()).unwrap();
+        assert_eq!(image, [0, 0, 0, 3, 1, 2, 3, 0xff]);
+
+        // The prefix takes room from the file.
+        a.size = 6;
+        let err = pack(&mut [a], &LayoutOpts::default()).unwrap_err();
+        fs::remove_file(file).unwrap();
+        assert!(
+            err.to_string()
+                .contains("it needs 7 bytes, file 3 + length prefix 4, of the area's 6"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn reproducible() {
+        let areas = || {
+            let mut fmap = area("area@0", None, 0x100);
+            fmap.compatible = Some(fmap::COMPATIBLE.to_string());
+            fmap.anchor = Some("top".to_string());
+            let mut info = area("area@1", Some(0), 0x100);
+            info.compatible = Some(build_info::COMPATIBLE.to_string());
+            vec![fmap, info]
+        };
+        let opts = LayoutOpts {
+            reproducible: true,
+            flash_size: Some(0x400),
+            ..Default::default()
+        };
+        let image = pack(&mut areas(), &opts).unwrap();
+        assert_eq!(image, pack(&mut areas(), &opts).unwrap());
+        // The FMAP lists area@1 first, as it comes first in the flash.
+        let first = 0x300 + 56 + 8;
+        assert_eq!(&image[first..first + 7], b"area@1\0");
+    }
+
+    #[test]
+    fn write_at_any_order() {
+        let mut forward = io::Cursor::new(Vec::new());
+        write_at(&mut forward, 4, &[1; 4]).unwrap();
+        write_at(&mut forward, 0, &[2; 2]).unwrap();
+        let mut backward = io::Cursor::new(Vec::new());
+        write_at(&mut backward, 0, &[2; 2]).unwrap();
+        write_at(&mut backward, 4, &[1; 4]).unwrap();
+        assert_eq!(forward.into_inner(), [2, 2, 0, 0, 1, 1, 1, 1]);
+        assert_eq!(backward.into_inner(), [2, 2, 0, 0, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn fill_pattern() {
+        let file = temp_path("fill-pattern");
+        fs::write(&file, [0; 2]).unwrap();
+        let mut areas = vec![area("area@0", Some(0), 5), area("area@1", None, 7)];
+        areas[0].fill_pattern = Some(vec![0xde, 0xad]);
+        areas[1].fill_pattern = Some(vec![1, 2, 3]);
+        areas[1].file = Some(file.to_str().unwrap().to_string());
+        let image = pack(&mut areas, &LayoutOpts::default()).unwrap();
+        fs::remove_file(file).unwrap();
+        // The file covers the start of area@1, leaving the pattern in phase with the area.
+        assert_eq!(image, [0xde, 0xad, 0xde, 0xad, 0xde, 0, 0, 3, 1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn chunked_fill() {
+        let sizes = [0, 1, FILL_CHUNK - 1, FILL_CHUNK, 3 * FILL_CHUNK + 5];
+        let mut areas: Vec<Area> = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, size)| area(&format!("area@{}", i), None, *size))
+            .collect();
+        areas[2].fill = Some(0);
+        areas[3].fill_pattern = Some(vec![1, 2, 3]);
+        areas[4].fill_pattern = Some((0..=0xfe).collect());
+        let image = pack(&mut areas, &LayoutOpts::default()).unwrap();
+        let want: Vec<u8> = areas.iter().flat_map(|a| a.filled()).collect();
+        assert!(image == want, "the chunked fill differs from Area::filled");
+    }
+
+    // Corrupting: an image that stores one byte wrong, as a worn-out flash
+    // might.
+    struct Corrupting {
+        image: io::Cursor<Vec<u8>>,
+        bad: u64,
+    }
+
+    impl Write for Corrupting {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let start = self.image.position();
+            let mut buf = buf.to_vec();
+            if let Some(b) = self
+                .bad
+                .checked_sub(start)
+                .and_then(|i| buf.get_mut(i as usize))
+            {
+                *b ^= 0x10;
+            }
+            self.image.write(&buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for Corrupting {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.image.read(buf)
+        }
+    }
+
+    impl Seek for Corrupting {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.image.seek(pos)
+        }
+    }
+
+    #[test]
+    fn verify_write() {
+        let areas = || vec![area("area@0", None, 0x10), area("area@1", None, 0x10)];
+        let mut image = Corrupting {
+            image: io::Cursor::new(Vec::new()),
+            bad: 0x13,
+        };
+        let opts = LayoutOpts::default();
+        write_image(&mut image, &mut areas(), &opts, &mut io::empty()).unwrap();
+
+        let opts = LayoutOpts {
+            verify_write: true,
+            ..opts
+        };
+        let err = write_image(&mut image, &mut areas(), &opts, &mut io::empty()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Area 'area@1' failed to verify: the byte at 0x13 reads back as 0xef, not 0xff"
+        );
+        image.bad = 0x100;
+        write_image(&mut image, &mut areas(), &opts, &mut io::empty()).unwrap();
+
+        // The GPT, written after the Areas, is verified too.
+        let mut image = Corrupting {
+            image: io::Cursor::new(Vec::new()),
+            bad: 0x200,
+        };
+        let mut a = area("area@4400", Some(0x4400), 0x1000);
+        a.partition = true;
+        a.type_guid = Some("0FC63DAF-8483-4772-8E79-3D69D8477DE4".to_string());
+        let gpt = LayoutOpts {
+            gpt: true,
+            flash_size: Some(0x10000),
+            ..opts.clone()
+        };
+        let err = write_image(&mut image, &mut [a], &gpt, &mut io::empty()).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("The GPT failed to verify: the byte at 0x200"),
+            "{}",
+            err
+        );
+
+        // A file the image is written to is opened for reading back too.
+        let out = temp_path("verify-write");
+        layout_flash(&out, &mut areas(), &opts, &mut io::empty()).unwrap();
+        fs::remove_file(out).unwrap();
+    }
+
+    #[test]
+    fn truncate_input() {
+        let file = temp_path("truncate-input");
+        fs::write(&file, (0..0x20).collect::<Vec<u8>>()).unwrap();
+        let mut a = area("area@0", Some(0), 0x10);
+        a.file = Some(file.to_str().unwrap().to_string());
+        assert!(pack(&mut [a.clone()], &LayoutOpts::default()).is_err());
+
+        a.truncate_input = true;
+        let image = pack(&mut [a], &LayoutOpts::default()).unwrap();
+        fs::remove_file(file).unwrap();
+        assert_eq!(image, (0..0x10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn mirror_at() {
+        let file = temp_path("mirror-area");
+        fs::write(&file, [1; 8]).unwrap();
+        let areas = || {
+            let mut a = area("area@0", Some(0), 0x10);
+            a.file = Some(file.to_str().unwrap().to_string());
+            vec![a, area("area@1", Some(0x20), 0x20)]
+        };
+        let opts = LayoutOpts {
+            mirror_at: Some(0x80),
+            flash_size: Some(0x100),
+            ..Default::default()
+        };
+        let primary = pack(&mut areas(), &LayoutOpts::default()).unwrap();
+        let image = pack(&mut areas(), &opts).unwrap();
+        assert_eq!(image.len(), 0xc0);
+        assert_eq!(image[..0x40], primary[..]);
+        assert_eq!(image[0x80..], primary[..]);
+
+        // The copy may neither overlap the image nor run past the flash.
+        for at in [0x20, 0xd0] {
+            let opts = LayoutOpts {
+                mirror_at: Some(at),
+                ..opts.clone()
+            };
+            assert!(pack(&mut areas(), &opts).is_err(), "{:#x}", at);
+        }
+
+        // The Areas are relocated once, not once for each time the
+        // image is written.
+        let relocated = LayoutOpts {
+            relocate: vec![parse_relocation("area@0=+0x10").unwrap()],
+            ..opts
+        };
+        let image = pack(&mut areas(), &relocated).unwrap();
+        assert_eq!(image.len(), 0xd0);
+        assert_eq!(image[0x10..0x18], [1; 8]);
+        assert_eq!(image[0x90..0x98], [1; 8]);
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn seed_image() {
+        let seed = temp_path("seed.bin");
+        let file = temp_path("seed-area");
+        fs::write(&seed, [0x5a; 0x40]).unwrap();
+        fs::write(&file, [1; 8]).unwrap();
+        let mut a = area("area@0", Some(0x10), 0x10);
+        a.file = Some(file.to_str().unwrap().to_string());
+        let opts = LayoutOpts {
+            seed_image: Some(seed.clone()),
+            ..Default::default()
+        };
+        let image = pack(&mut [a], &opts).unwrap();
+        fs::remove_file(seed).unwrap();
+        fs::remove_file(file).unwrap();
+        assert_eq!(image.len(), 0x40);
+        assert_eq!(image[..0x10], [0x5a; 0x10]);
+        assert_eq!(image[0x10..0x18], [1; 8]);
+        assert_eq!(image[0x18..0x20], [0xff; 8]);
+        assert_eq!(image[0x20..], [0x5a; 0x20]);
+    }
+}
@@ -0,0 +1,35 @@
+pub mod area;
+pub mod area_index;
+pub mod build_info;
+pub mod cbfs;
+pub mod checksums;
+pub mod compress;
+pub mod dtb;
+pub mod error;
+pub mod extract;
+pub mod flashrom;
+pub mod fmap;
+pub mod gpt;
+pub mod image_meta;
+pub mod kconfig;
+pub mod layout;
+pub mod map;
+pub mod mode;
+pub mod report;
+pub mod scaffold;
+pub mod schema;
+pub mod size;
+pub mod sniff;
+#[cfg(test)]
+mod testutil;
+pub mod trailer;
+pub mod warnings;
+
+pub use area::{read_areas, Area, AreaOpts};
+pub use error::{LayoutError, Result};
+pub use layout::{
+    compute_layout, layout_flash, layout_flash_compressed, pack, LayoutOpts, Summary,
+};
+pub use map::write_map;
+pub use report::{report, Report};
+pub use size::parse_size;
@@ -1,167 +1,326 @@
 use clap::Parser;
-use std::io::{self, Seek, SeekFrom, Write};
+use clap_verbosity_flag::{Verbosity, WarnLevel};
+use layoutflash_tool::area::{read_flash_info, read_metadata};
+use layoutflash_tool::compress::Compression;
+use layoutflash_tool::extract::extract;
+use layoutflash_tool::flashrom::write_flashrom_layout;
+use layoutflash_tool::image_meta::fdt_hash;
+use layoutflash_tool::kconfig::write_kconfig;
+use layoutflash_tool::layout::{diff_areas, suggest_offsets};
+use layoutflash_tool::mode::set_mode;
+use layoutflash_tool::report::Suggestion;
+use layoutflash_tool::scaffold::scaffold;
+use layoutflash_tool::schema::read_schema;
+use layoutflash_tool::warnings::{check_warnings, CountingLogger};
+use layoutflash_tool::{
+    compute_layout, layout_flash, layout_flash_compressed, read_areas, report, write_map, Area,
+    AreaOpts, LayoutError, LayoutOpts, Report, Result,
+};
+use log::{error, LevelFilter};
+use std::io;
 use std::process::exit;
 use std::{
-    env, fs,
+    fs,
     path::{Path, PathBuf},
 };
 
-// NOTE: we don't use u32. At the rate that SPI flash is expanding, we're going to see
-// 5B addressing soon I bet. The size limitation should be a function of the destination,
-// not this program. This problem should just stupidly arrange things.
-struct Area {
-    pub name: String,
-    pub offset: Option<usize>,
-    pub size: usize,
-    pub file: Option<String>,
+// read: the Areas of an fdt, and the layout options completed from it.
+fn read(in_fdt: &Path, args: &Opts) -> Result<(Vec<Area>, LayoutOpts)> {
+    let areas = read_areas(in_fdt, &args.areas)?;
+    let layout = LayoutOpts {
+        strict: args.areas.strict,
+        fdt_hash: Some(fdt_hash(in_fdt)?),
+        ..args
+            .layout
+            .with_flash_info(&read_flash_info(in_fdt, &args.areas)?)
+    };
+    Ok((areas, layout))
 }
 
-// In earlier versions of this function, we assumed all Areas had a non-zero
-// offset. There was a sort step to sort by offset as a first step.
-// Requiring users to compute all the offsets, and adjust them every time
-// something changed size, was incredibly inconvenient.
-// Now, we allow a zero offset, but it turns out that sorting is the wrong
-// thing to do: users tend to specify the areas in the dts in the order
-// they need to be in ROM; further, the rule of "put this area here,
-// and everything after it, after it" will not work if we sort: all
-// offset 0 items will be placed first!
-// So, new rules:
-// This method assumes that the areas are specified in order for a reason.
-// In most cases, the offset is 0, meaning "use the previous area end+1
-// as our offset". In rare cases, an offset is specified; for that, ensure
-// there is no overlap with previous Areas. All Areas after this Area will
-// be placed after it. That way, only a limited number of offsets need
-// to be specified, possibly even 0, and the order in the ROM image will be the order
-// specified in the DTS.
-fn layout_flash(path: &Path, areas: &mut Vec<Area>) -> io::Result<()> {
-    let mut f = fs::File::create(path)?;
-    let mut last_area_end = 0;
-    for a in areas {
-        println!("Area {:?}: @{:?}, size {:?}", a.name, a.offset, a.size);
-        let offset = match a.offset {
-            Some(x) => x,
-            None => last_area_end,
-        };
-        if offset < last_area_end {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Areas are overlapping, last area finished at offset {}, next area '{}' starts at {}", last_area_end, a.name, offset)));
+fn diff(in_fdt: &Path, old: &Path, new: &Path, args: &Opts) -> Result<()> {
+    let (mut areas, layout) = read(in_fdt, args)?;
+    compute_layout(&mut areas, &layout)?;
+    let old = fs::read(old)?;
+    let new = fs::read(new)?;
+    for d in diff_areas(&areas, &old, &new) {
+        match d.first {
+            None => println!("{}: identical", d.name),
+            Some(first) => println!(
+                "{}: {} bytes differ, starting at {:#x}",
+                d.name, d.count, first
+            ),
         }
-        last_area_end = offset + a.size;
-
-        println!("<{}> @ 0x{:x}", a.name, last_area_end);
-        // First fill with 0xff.
-        let mut v = Vec::new();
-        v.resize(a.size as usize, 0xff);
-        f.seek(SeekFrom::Start(offset as u64))?;
-        f.write_all(&v)?;
-
-        // If a file is specified, write the file.
-        if let Some(path) = &a.file {
-            let mut path = path.to_string();
-            // Allow environment variables in the path.
-            for (key, value) in env::vars() {
-                path = str::replace(&path, &format!("$({})", key), &value);
-            }
+    }
+    Ok(())
+}
 
-            // If the path is an unused environment variable, skip it.
-            if path.starts_with("$(") && path.ends_with(')') {
-                continue;
-            }
+fn extract_areas(in_fdt: &Path, image: &Path, out_dir: &Path, args: &Opts) -> Result<()> {
+    let (mut areas, layout) = read(in_fdt, args)?;
+    compute_layout(&mut areas, &layout)?;
+    extract(&areas, &fs::read(image)?, out_dir)
+}
 
-            f.seek(SeekFrom::Start(offset as u64))?;
-            let data = match fs::read(&path) {
-                Err(e) => {
-                    return Err(io::Error::new(
-                        e.kind(),
-                        format!("Could not open: {}", path),
-                    ))
-                }
-                Ok(data) => data,
-            };
-            if data.len() > a.size as usize {
-                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("File {} is too big to fit into the flash area, file size: {}, area size: {}", path, data.len(), a.size)));
-            }
-            f.write_all(&data)?;
+fn list(in_fdt: &Path, json: bool, args: &Opts) -> Result<()> {
+    let (mut areas, layout) = read(in_fdt, args)?;
+    compute_layout(&mut areas, &layout)?;
+    if json {
+        let meta = read_metadata(in_fdt, &args.areas)?;
+        let mut report = Report {
+            model: meta.model,
+            layout_version: meta.layout_version,
+            ..report(&areas, vec![])
+        };
+        if layout.reproducible {
+            report = report.reproducible();
         }
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(io::Error::from)?
+        );
+    } else {
+        write_map(&mut io::stdout(), &areas)?;
     }
     Ok(())
 }
 
-fn create_areas(fdt: &fdt::Fdt) -> io::Result<Vec<Area>> {
-    // Assemble the bits of the fdt we care about into Areas.
-    let mut areas: Vec<Area> = vec![];
-
-    for node in fdt.find_all_nodes("/flash-info/areas") {
-        println!("{:?}", node.name);
-        for child in node.children() {
-            println!("    {}", child.name);
-            let mut a: Area = Area {
-                name: child.name.to_string(),
-                offset: None,
-                size: 0,
-                file: None,
-            };
-            for p in child.properties() {
-                println!(" {:?} {:?}, {:?}", p.name, p.as_str(), p.as_usize());
-
-                // There can be all kinds of properties in a node.
-                // we only care about file, size, and offset.
-                // Not that we remove any, just that those relate
-                // to data we put in the image.
-
-                match p.name {
-                    "file" => {
-                        a.file = Some(
-                            p.as_str()
-                                .expect(
-                                    format!("Child {}: \"file\" needs a name", child.name).as_str(),
-                                )
-                                .to_string(),
-                        );
-                    }
-                    "offset" => {
-                        a.offset = Some(p.as_usize().unwrap());
-                    }
-                    "size" => {
-                        a.size = p.as_usize().unwrap();
-                    }
-                    _ => {}
-                }
-            }
-            areas.push(a);
+// validate fails if the areas can't be read or laid out, or with
+// --schema if they break the schema. With --json the errors are
+// reported in the document too.
+fn validate(in_fdt: &Path, json: bool, schema: bool, args: &Opts) -> Result<()> {
+    let violations = if schema {
+        read_schema(in_fdt, &args.areas)?
+    } else {
+        vec![]
+    };
+    let (areas, layout, result) = match read(in_fdt, args) {
+        Ok((mut areas, layout)) => {
+            let result = compute_layout(&mut areas, &layout);
+            (areas, layout, result)
+        }
+        Err(e) => (vec![], args.layout.clone(), Err(e)),
+    };
+    if json {
+        let errors = violations
+            .iter()
+            .cloned()
+            .chain(
+                result
+                    .as_ref()
+                    .err()
+                    .map(|e| e.messages())
+                    .unwrap_or_default(),
+            )
+            .collect();
+        let mut report = report(&areas, errors);
+        if result.is_err() {
+            report.suggestions = suggest_offsets(&areas, &layout)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(name, offset)| Suggestion { name, offset })
+                .collect();
+        }
+        if layout.reproducible {
+            report = report.reproducible();
+        }
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(io::Error::from)?
+        );
+    } else {
+        for v in &violations {
+            error!("{}", v);
+        }
+        if result.is_ok() && violations.is_empty() {
+            println!("{}: ok", in_fdt.display());
         }
     }
-
-    Ok(areas)
+    result?;
+    if !violations.is_empty() {
+        return Err(LayoutError::Area(format!(
+            "{} schema violation(s)",
+            violations.len()
+        )));
+    }
+    Ok(())
 }
 
 #[derive(clap::Parser)]
-#[clap(version)]
+#[clap(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Opts {
     /// The path to the firmware device tree file
-    in_fdt: PathBuf,
+    #[clap(required = true)]
+    in_fdt: Option<PathBuf>,
     // This refuses to work, and the error makes no sense.
     // #[clap(Arg::parse(from_os_str))]
     /// The output path for the firmware
-    out_firmware: PathBuf,
+    #[clap(required = true)]
+    out_firmware: Option<PathBuf>,
+    /// Write a map of the resolved areas and the gaps between them
+    #[clap(long)]
+    map: Option<PathBuf>,
+    /// Write the resolved offsets and sizes as a defconfig fragment of
+    /// CONFIG_<AREA>_OFFSET and CONFIG_<AREA>_SIZE lines
+    #[clap(long)]
+    export_kconfig: Option<PathBuf>,
+    /// Write a flashrom layout file of the resolved areas, for
+    /// `flashrom --layout <FILE> --image <NAME>`
+    #[clap(long)]
+    flashrom_layout: Option<PathBuf>,
+    #[clap(flatten)]
+    areas: AreaOpts,
+    #[clap(flatten)]
+    layout: LayoutOpts,
+    // -v prints each area as it is written, -q only prints errors.
+    #[clap(flatten)]
+    verbose: Verbosity<WarnLevel>,
+    /// Fail if any warnings were logged, e.g. for overlaps with --allow-overlap
+    #[clap(long, global = true)]
+    fail_on_warning: bool,
+    /// Print a summary of the image size, fill and gaps when done
+    #[clap(long)]
+    summary: bool,
+    /// List the areas whose file or pad-file was skipped because it names
+    /// an environment variable that isn't set
+    #[clap(long)]
+    list_unresolved_env: bool,
+    /// Compress the finished image, writing it to <OUT_FIRMWARE>.gz or .zst
+    #[clap(long, value_enum)]
+    compress_output: Option<Compression>,
+    /// Set the permissions of the written image, in octal, e.g. 0444
+    /// [default: as created, following the umask]
+    #[clap(long, value_parser = layoutflash_tool::mode::parse_mode)]
+    mode: Option<u32>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Compare two firmware images area by area
+    Diff {
+        /// The path to the firmware device tree file both images were built from
+        in_fdt: PathBuf,
+        old: PathBuf,
+        new: PathBuf,
+    },
+    /// Write a starting fdt for a new layout, with the areas placed one
+    /// after the other and their files taken from the environment
+    Scaffold {
+        /// The size of the flash
+        #[clap(long, value_parser = layoutflash_tool::parse_size)]
+        flash_size: u64,
+        /// The path to write the firmware device tree file to
+        out_fdt: PathBuf,
+        /// The areas, as name:size, e.g. bootblob:64K
+        #[clap(value_parser = layoutflash_tool::scaffold::parse_area_spec)]
+        areas: Vec<(String, u64)>,
+    },
+    /// Write each area of a firmware image to its own file, named after
+    /// its description
+    Extract {
+        /// The path to the firmware device tree file the image was built from
+        in_fdt: PathBuf,
+        image: PathBuf,
+        /// The directory to write the area files to
+        out_dir: PathBuf,
+    },
+    /// List the resolved areas and the gaps between them
+    List {
+        /// The path to the firmware device tree file
+        in_fdt: PathBuf,
+        /// Print a JSON document instead of a map
+        #[clap(long)]
+        json: bool,
+    },
+    /// Check that the areas can be read and laid out
+    Validate {
+        /// The path to the firmware device tree file
+        in_fdt: PathBuf,
+        /// Print a JSON document of the areas and any errors
+        #[clap(long)]
+        json: bool,
+        /// Also check the area properties against the schema, reporting
+        /// every violation
+        #[clap(long)]
+        schema: bool,
+    },
+}
+
+fn build(args: &Opts) -> Result<()> {
+    // clap requires both paths when there is no subcommand.
+    let in_fdt = args.in_fdt.as_ref().unwrap();
+    let out_firmware = args.out_firmware.as_ref().unwrap();
+    let (mut areas, layout) = read(in_fdt, args)?;
+    let (summary, written) = match args.compress_output {
+        Some(c) => (
+            layout_flash_compressed(out_firmware, &mut areas, &layout, &mut io::stdin(), c)?,
+            c.path(out_firmware),
+        ),
+        None => (
+            layout_flash(out_firmware, &mut areas, &layout, &mut io::stdin())?,
+            out_firmware.clone(),
+        ),
+    };
+    if let Some(mode) = args.mode {
+        set_mode(&written, mode)?;
+    }
+    if args.summary {
+        println!("{}", summary);
+    }
+    if args.list_unresolved_env {
+        for (area, path) in &summary.unresolved {
+            println!("{}: {} is not set, left filled", area, path);
+        }
+    }
+    if let Some(map) = &args.map {
+        write_map(&mut fs::File::create(map)?, &areas)?;
+    }
+    if let Some(kconfig) = &args.export_kconfig {
+        write_kconfig(&mut fs::File::create(kconfig)?, &areas)?;
+    }
+    if let Some(layout) = &args.flashrom_layout {
+        write_flashrom_layout(&mut fs::File::create(layout)?, &areas)?;
+    }
+    Ok(())
 }
 
 fn main() {
     let args = Opts::parse();
+    let level = args.verbose.log_level_filter();
+    let logger = Box::leak(Box::new(CountingLogger::new(
+        env_logger::Builder::new().filter_level(level).build(),
+    )));
+    log::set_logger(logger).expect("the logger is only set once");
+    log::set_max_level(level.max(LevelFilter::Warn));
 
-    println!("Read in {:?}", args.in_fdt);
-    let data = fs::read(&args.in_fdt).unwrap();
-    let fdt = match fdt::Fdt::new(&data) {
-        Ok(f) => f,
-        Err(error) => {
-            eprintln!("fdt from data failed: {}", error);
-            exit(1);
-        }
+    let result = match &args.command {
+        Some(Command::Diff { in_fdt, old, new }) => diff(in_fdt, old, new, &args),
+        Some(Command::Scaffold {
+            flash_size,
+            out_fdt,
+            areas,
+        }) => scaffold(*flash_size, areas)
+            .and_then(|dtb| fs::write(out_fdt, dtb).map_err(LayoutError::from)),
+        Some(Command::Extract {
+            in_fdt,
+            image,
+            out_dir,
+        }) => extract_areas(in_fdt, image, out_dir, &args),
+        Some(Command::List { in_fdt, json }) => list(in_fdt, *json, &args),
+        Some(Command::Validate {
+            in_fdt,
+            json,
+            schema,
+        }) => validate(in_fdt, *json, *schema, &args),
+        None => build(&args),
     };
-
-    create_areas(&fdt)
-        .and_then(|mut areas| layout_flash(&args.out_firmware, &mut areas))
-        .unwrap_or_else(|err| {
-            eprintln!("failed: {}", err);
-            exit(1);
-        });
+    let result = result.and_then(|()| check_warnings(logger.warnings(), args.fail_on_warning));
+    result.unwrap_or_else(|err| {
+        error!("failed: {}", err);
+        exit(1);
+    });
 }
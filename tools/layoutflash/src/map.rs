@@ -0,0 +1,71 @@
+use crate::area::Area;
+use crate::error::Result;
+use std::io::Write;
+use std::ops::Range;
+
+// write_map: write a plain-text map of the resolved areas, one
+// `start-end description` line per area or gap, sorted by offset.
+// As in /proc/<pid>/maps, the end offset is exclusive.
+pub fn write_map(w: &mut impl Write, areas: &[Area]) -> Result<()> {
+    let mut lines: Vec<(usize, usize, Option<&str>)> = areas
+        .iter()
+        .map(|a| {
+            let offset = a.offset.unwrap();
+            (offset, offset + a.size, Some(a.label()))
+        })
+        .collect();
+    lines.extend(gaps(areas).into_iter().map(|g| (g.start, g.end, None)));
+    // A gap starts where the areas before it end, so it comes after an
+    // empty area at the same offset.
+    lines.sort_by_key(|(start, _, what)| (*start, what.is_none()));
+    for (start, end, what) in lines {
+        writeln!(w, "{:08x}-{:08x} {}", start, end, what.unwrap_or("(gap)"))?;
+    }
+    Ok(())
+}
+
+// gaps: the ranges between the placed areas that no area covers.
+pub fn gaps(areas: &[Area]) -> Vec<Range<usize>> {
+    let mut sorted: Vec<&Area> = areas.iter().filter(|a| a.offset.is_some()).collect();
+    sorted.sort_by_key(|a| a.offset);
+    let mut gaps = Vec::new();
+    let mut last_area_end = 0;
+    for a in sorted {
+        let offset = a.offset.unwrap();
+        if offset > last_area_end {
+            gaps.push(last_area_end..offset);
+        }
+        last_area_end = last_area_end.max(offset + a.size);
+    }
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{compute_layout, LayoutOpts};
+    use crate::testutil::area;
+
+    #[test]
+    fn map_lists_areas_and_gaps() {
+        let mut areas = vec![
+            area("area@0", Some(0x100), 0x100),
+            area("area@1", None, 0x200),
+            area("area@2", Some(0x1000), 0x1000),
+        ];
+        areas[0].description = Some("Boot Blob".to_string());
+        compute_layout(&mut areas, &LayoutOpts::default()).unwrap();
+
+        let mut out = Vec::new();
+        write_map(&mut out, &areas).unwrap();
+        let map = String::from_utf8(out).unwrap();
+        let want = vec![
+            "00000000-00000100 (gap)",
+            "00000100-00000200 Boot Blob",
+            "00000200-00000400 area@1",
+            "00000400-00001000 (gap)",
+            "00001000-00002000 area@2",
+        ];
+        assert_eq!(map.lines().collect::<Vec<_>>(), want);
+    }
+}
@@ -0,0 +1,64 @@
+use crate::error::Result;
+use log::debug;
+use std::path::Path;
+
+// parse_mode: an octal file mode on the command line, e.g. 0644 or 755.
+pub fn parse_mode(s: &str) -> std::result::Result<u32, String> {
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 && !digits.is_empty() => Ok(mode),
+        _ => Err(format!(
+            "invalid mode '{}': expected octal permissions up to 7777",
+            s
+        )),
+    }
+}
+
+// set_mode: set a file's permissions. Where files have no Unix
+// permissions the mode is ignored.
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    debug!("setting the mode of {} to {:o}", path.display(), mode);
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    debug!(
+        "not setting the mode of {} to {:o}: there are no Unix permissions",
+        path.display(),
+        mode
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::temp_path;
+    use std::fs;
+
+    #[test]
+    fn modes() {
+        assert_eq!(parse_mode("0644"), Ok(0o644));
+        assert_eq!(parse_mode("755"), Ok(0o755));
+        assert_eq!(parse_mode("0o400"), Ok(0o400));
+        for bad in ["", "0o", "8", "0x644", "17777", "rw-r--r--"] {
+            assert!(parse_mode(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn set_file_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = temp_path("mode");
+        fs::write(&path, [0]).unwrap();
+        set_mode(&path, 0o640).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        fs::remove_file(path).unwrap();
+        assert_eq!(mode & 0o7777, 0o640);
+    }
+}
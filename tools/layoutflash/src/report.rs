@@ -0,0 +1,145 @@
+use crate::area::Area;
+use crate::cbfs::area_type;
+use crate::map::gaps;
+use serde::Serialize;
+use std::path::Path;
+
+// Report: the resolved layout as a stable JSON document, for scripts.
+// Fields are only ever added, never renamed or removed.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    // The root model and layout-version, if the fdt has them.
+    pub model: Option<String>,
+    pub layout_version: Option<u32>,
+    pub areas: Vec<AreaReport>,
+    pub gaps: Vec<Gap>,
+    pub errors: Vec<String>,
+    // Conflict-free offsets for every Area, when the layout failed.
+    pub suggestions: Vec<Suggestion>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AreaReport {
+    pub name: String,
+    // None if the layout failed before the Area was placed.
+    pub offset: Option<usize>,
+    pub size: usize,
+    pub compatible: Option<String>,
+    pub description: Option<String>,
+    pub file: Option<String>,
+    // The boolean properties set on the Area, e.g. "crc-trailer".
+    pub flags: Vec<String>,
+    // The CBFS file type, from `type`, and its cbfstool constant.
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub cbfs_type: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Suggestion {
+    pub name: String,
+    pub offset: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Gap {
+    pub offset: usize,
+    pub size: usize,
+}
+
+impl Report {
+    // reproducible: drop what depends on where the build ran, leaving
+    // only the file names of the Areas' files, and list the Areas in
+    // offset order.
+    pub fn reproducible(mut self) -> Report {
+        for a in &mut self.areas {
+            a.file = a.file.as_deref().map(|f| {
+                Path::new(f)
+                    .file_name()
+                    .map_or(f.to_string(), |n| n.to_string_lossy().into_owned())
+            });
+        }
+        self.areas.sort_by_key(|a| a.offset);
+        self
+    }
+}
+
+// report: describe the Areas and the errors found laying them out.
+pub fn report(areas: &[Area], errors: Vec<String>) -> Report {
+    Report {
+        model: None,
+        layout_version: None,
+        areas: areas
+            .iter()
+            .map(|a| AreaReport {
+                name: a.name.clone(),
+                offset: a.offset,
+                size: a.size,
+                compatible: a.compatible.clone(),
+                description: a.description.clone(),
+                file: a.file.clone(),
+                flags: a.flags().iter().map(|f| f.to_string()).collect(),
+                type_: a.type_.clone().unwrap_or_else(|| "raw".to_string()),
+                cbfs_type: area_type(a),
+            })
+            .collect(),
+        gaps: gaps(areas)
+            .into_iter()
+            .map(|g| Gap {
+                offset: g.start,
+                size: g.len(),
+            })
+            .collect(),
+        errors,
+        suggestions: vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{compute_layout, LayoutOpts};
+    use crate::testutil::area;
+
+    #[test]
+    fn json_report() {
+        let mut areas = vec![
+            area("area@0", Some(0), 0x100),
+            area("area@1", Some(0x200), 0x20),
+        ];
+        areas[0].compatible = Some("oreboot,dt-overlay".to_string());
+        areas[1].crc_trailer = true;
+        areas[1].type_ = Some("payload".to_string());
+        compute_layout(&mut areas, &LayoutOpts::default()).unwrap();
+
+        let json = serde_json::to_string_pretty(&report(&areas, vec!["oops".to_string()])).unwrap();
+        let v: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(v["areas"][0]["name"], "area@0");
+        assert_eq!(v["areas"][0]["compatible"], "oreboot,dt-overlay");
+        assert!(v["areas"][0]["description"].is_null());
+        assert_eq!(v["areas"][1]["offset"], 0x200);
+        assert_eq!(v["areas"][1]["size"], 0x20);
+        assert_eq!(v["areas"][1]["flags"][0], "crc-trailer");
+        assert_eq!(v["areas"][1]["type"], "payload");
+        assert_eq!(v["areas"][1]["cbfs_type"], 0x20);
+        assert_eq!(v["gaps"][0]["offset"], 0x100);
+        assert_eq!(v["gaps"][0]["size"], 0x100);
+        assert_eq!(v["errors"][0], "oops");
+    }
+
+    #[test]
+    fn reproducible_report() {
+        let areas = |dir: &str| {
+            let mut a = area("area@0", Some(0x100), 0x100);
+            a.file = Some(format!("{}/payload.bin", dir));
+            vec![a, area("area@1", Some(0), 0x100)]
+        };
+        let json = |dir| {
+            serde_json::to_string_pretty(&report(&areas(dir), vec![]).reproducible()).unwrap()
+        };
+        assert_eq!(json("/home/a/build"), json("/tmp/b"));
+        let v: serde_json::Value = serde_json::from_str(&json("/tmp/b")).unwrap();
+        assert_eq!(v["areas"][0]["name"], "area@1");
+        assert_eq!(v["areas"][1]["file"], "payload.bin");
+    }
+}
@@ -0,0 +1,116 @@
+use crate::dtb::DtbWriter;
+use crate::error::{LayoutError, Result};
+use crate::extract::sanitize;
+use crate::kconfig::symbol;
+use crate::size::parse_size;
+
+// parse_area_spec: a `name:size` pair from the command line, e.g.
+// "bootblob:64K".
+pub fn parse_area_spec(s: &str) -> std::result::Result<(String, u64), String> {
+    let (name, size) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("'{}' should be name:size", s))?;
+    if name.is_empty() {
+        return Err(format!("'{}' has no name", s));
+    }
+    Ok((name.to_string(), parse_size(size)?))
+}
+
+// scaffold: a starting point for a new board's layout, an fdt with a
+// flash of `flash_size` bytes holding the named Areas one after the
+// other. Each Area gets an explicit offset, and a file of "$(NAME)",
+// its sanitized name uppercased, so the files can be given in the
+// environment until the fdt is filled in.
+pub fn scaffold(flash_size: u64, areas: &[(String, u64)]) -> Result<Vec<u8>> {
+    let mut w = DtbWriter::new();
+    w.begin_node("");
+    w.property_u32("#address-cells", 2);
+    w.property_u32("#size-cells", 2);
+    w.begin_node("flash-info");
+    w.property_str("compatible", "ore-flashinfo");
+    let mut reg = 0u64.to_be_bytes().to_vec();
+    reg.extend_from_slice(&flash_size.to_be_bytes());
+    w.property("reg", &reg);
+    w.begin_node("areas");
+    let mut offset = 0u64;
+    for (name, size) in areas {
+        let end = offset.checked_add(*size).ok_or_else(|| {
+            LayoutError::Input(format!(
+                "Area '{}' of {:#x} bytes at {:#x} ends past any flash",
+                name, size, offset
+            ))
+        })?;
+        if end > flash_size {
+            return Err(LayoutError::Input(format!(
+                "Area '{}' ends at {:#x}, past the end of the {:#x} byte flash",
+                name, end, flash_size
+            )));
+        }
+        w.begin_node(&format!("area@{:x}", offset));
+        w.property_str("description", name);
+        number(&mut w, "offset", offset);
+        number(&mut w, "size", *size);
+        w.property_str("file", &format!("$({})", symbol(&sanitize(name))));
+        w.end_node();
+        offset = end;
+    }
+    w.end_node();
+    w.end_node();
+    w.end_node();
+    Ok(w.finish())
+}
+
+// number: a number property, in one cell if it fits.
+fn number(w: &mut DtbWriter, name: &str, n: u64) {
+    match u32::try_from(n) {
+        Ok(n) => w.property_u32(name, n),
+        Err(_) => w.property_u64(name, n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::area::{create_areas, flash_info, AreaOpts};
+
+    #[test]
+    fn round_trip() {
+        let spec = ["boot-blob:4K", "payload:0x8000"].map(|s| parse_area_spec(s).unwrap());
+        let dtb = scaffold(0x10000, &spec).unwrap();
+        let fdt = fdt::Fdt::new(&dtb).unwrap();
+        let areas = create_areas(&fdt, &AreaOpts::default()).unwrap();
+        let got: Vec<_> = areas
+            .iter()
+            .map(|a| {
+                (
+                    a.name.as_str(),
+                    a.label(),
+                    a.offset,
+                    a.size,
+                    a.file.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            got,
+            [
+                ("area@0", "boot-blob", Some(0), 0x1000, Some("$(BOOT_BLOB)")),
+                (
+                    "area@1000",
+                    "payload",
+                    Some(0x1000),
+                    0x8000,
+                    Some("$(PAYLOAD)")
+                ),
+            ]
+        );
+        assert_eq!(flash_info(&fdt).unwrap().reg, Some((0, 0x10000)));
+
+        assert!(scaffold(0x8000, &spec).is_err());
+        let huge = [("a".to_string(), 0x10), ("b".to_string(), u64::MAX)];
+        let err = scaffold(u64::MAX, &huge).unwrap_err();
+        assert!(matches!(err, LayoutError::Input(_)), "{}", err);
+        assert!(parse_area_spec("payload").is_err());
+        assert!(parse_area_spec(":4K").is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use crate::area::{is_group, read_fdt, set_property, Area, AreaOpts};
+use crate::error::{LayoutError, Result};
+use fdt::node::FdtNode;
+use std::path::Path;
+
+// The values a `compression` property may take.
+pub const COMPRESSIONS: &[&str] = &["gzip", "lz4", "lzma", "zstd"];
+
+// The values a `status` property may take.
+pub const STATUSES: &[&str] = &["okay", "ok", "disabled"];
+
+// The values a `length-prefix` property may take.
+pub const LENGTH_PREFIXES: &[&str] = &["none", "u32-le", "u32-be"];
+
+// check_schema: every way the Area nodes of an fdt break the schema, in
+// one pass, rather than the first as when reading the Areas. Known
+// properties must have the right type and a known value where there is a
+// fixed set, as set_property, which reads them, decides; and each Area
+// needs a size or an end. Unknown properties are allowed. Only the fdt's
+// own Areas are checked, not those it imports.
+pub fn check_schema(fdt: &fdt::Fdt) -> Vec<String> {
+    let mut violations = vec![];
+    for node in fdt.find_all_nodes("/flash-info/areas") {
+        check_nodes(node, &mut violations);
+    }
+    violations
+}
+
+fn check_nodes(node: FdtNode, violations: &mut Vec<String>) {
+    for child in node.children() {
+        let mut a = Area {
+            name: child.name.to_string(),
+            ..Default::default()
+        };
+        for p in child.properties() {
+            if let Err(e) = set_property(&mut a, &p) {
+                violations.push(e.to_string());
+            }
+        }
+        if is_group(child.name) {
+            check_nodes(child, violations);
+        } else if child.property("size").is_none() && child.property("end").is_none() {
+            violations.push(format!("Area '{}' needs a size or an end", child.name));
+        }
+    }
+}
+
+// read_schema: check_schema for the fdt at a path.
+pub fn read_schema(path: &Path, opts: &AreaOpts) -> Result<Vec<String>> {
+    let data = read_fdt(path, opts)?;
+    let fdt = fdt::Fdt::new(&data)
+        .map_err(|e| LayoutError::Fdt(format!("fdt from data failed: {}", e)))?;
+    Ok(check_schema(&fdt))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn all_violations() {
+        let violations =
+            read_schema(Path::new("src/testdata/schema.dtb"), &AreaOpts::default()).unwrap();
+        assert_eq!(
+            violations,
+            [
+                "Area 'area@0': property 'size' should be a 32 or 64 bit number, but is 2 bytes long",
+                "Area 'area@0': property 'compression' should be one of gzip, lz4, lzma, zstd, but is 'xz'",
+                "Area 'area@1': property 'offset' should be a 32 or 64 bit number, but is 12 bytes long",
+                "Area 'area@1': property 'read-only' should be empty, but is 4 bytes long",
+                "Area 'area@1' needs a size or an end",
+                "Area 'group@0': property 'fill' should be a byte, but is 0x100",
+                "Area 'area@2': property 'file' should be a string, but is 2 bytes long",
+                "Area 'area@2': property 'pad-file' should be a string, but is 2 bytes long",
+                "Area 'area@3': property 'type' should be one of raw, stage, payload, fsp, but is 'elf'",
+                "Area 'area@3': property 'type-guid' should be a GUID, but is 'not-a-guid'",
+                "Area 'area@4': property 'fill-pattern' should be a non-empty byte string, but is empty",
+            ]
+        );
+
+        for ok in ["src/testdata/group.dtb", "src/testdata/status.dtb"] {
+            let violations = read_schema(Path::new(ok), &AreaOpts::default()).unwrap();
+            assert!(violations.is_empty(), "{}: {:?}", ok, violations);
+        }
+    }
+}
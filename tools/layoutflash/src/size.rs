@@ -0,0 +1,48 @@
+// Byte counts on the command line: a decimal or 0x-prefixed hex number,
+// optionally followed by a K, M or G suffix. Flash parts are sized in
+// powers of two, so the suffixes are binary; Ki, Mi and Gi are accepted
+// as aliases.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    const SUFFIXES: &[(&str, u32)] = &[
+        ("Ki", 10),
+        ("Mi", 20),
+        ("Gi", 30),
+        ("K", 10),
+        ("M", 20),
+        ("G", 30),
+    ];
+    let (digits, shift) = SUFFIXES
+        .iter()
+        .find_map(|(suffix, shift)| s.strip_suffix(suffix).map(|d| (d, *shift)))
+        .unwrap_or((s, 0));
+    let n = match digits.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => digits.parse(),
+    }
+    .map_err(|e| format!("invalid size '{}': {}", s, e))?;
+    n.checked_mul(1 << shift)
+        .ok_or_else(|| format!("size '{}' is too large", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("0x1000"), Ok(0x1000));
+        assert_eq!(parse_size("0xffab"), Ok(0xffab));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("64Ki"), Ok(64 << 10));
+        assert_eq!(parse_size("16M"), Ok(16 << 20));
+        assert_eq!(parse_size("16Mi"), Ok(16 << 20));
+        assert_eq!(parse_size("2G"), Ok(2 << 30));
+        assert_eq!(parse_size("1Gi"), Ok(1 << 30));
+        assert_eq!(parse_size("0x10K"), Ok(0x10 << 10));
+
+        for bad in ["", "K", "12Q", "0x", "ten", "1.5M", "-1", "0xffffffffffffM"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
+    }
+}
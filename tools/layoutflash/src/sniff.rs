@@ -0,0 +1,38 @@
+// hint: a best-effort guess at what a file is, for error messages about
+// files that don't fit. None if nothing stands out.
+pub fn hint(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x7fELF") {
+        return Some(
+            "the file appears to be an ELF; did you mean to use a raw binary, e.g. from objcopy -O binary?",
+        );
+    }
+    if data.starts_with(&[0xd0, 0x0d, 0xfe, 0xed]) {
+        return Some("the file appears to be a device tree blob");
+    }
+    // Most text has few bytes outside printable ASCII and whitespace.
+    let head = &data[..data.len().min(512)];
+    let printable = head
+        .iter()
+        .filter(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+        .count();
+    if !head.is_empty() && printable * 100 >= head.len() * 95 {
+        return Some("the file appears to be text, not a binary");
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hints() {
+        assert!(hint(b"\x7fELF\x02\x01\x01\0").unwrap().contains("ELF"));
+        assert!(hint(&[0xd0, 0x0d, 0xfe, 0xed, 0, 0])
+            .unwrap()
+            .contains("device tree"));
+        assert!(hint(b"/dts-v1/;\n/ {\n};\n").unwrap().contains("text"));
+        assert_eq!(hint(&[0, 1, 2, 0xff, 0x80]), None);
+        assert_eq!(hint(&[]), None);
+    }
+}
@@ -0,0 +1,16 @@
+use crate::area::Area;
+use std::{env, path::PathBuf};
+
+pub fn area(name: &str, offset: Option<usize>, size: usize) -> Area {
+    Area {
+        name: name.to_string(),
+        offset,
+        size,
+        ..Default::default()
+    }
+}
+
+// A path in the temporary directory that is unique to this test run.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("layoutflash-{}-{}", std::process::id(), name))
+}
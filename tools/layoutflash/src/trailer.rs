@@ -0,0 +1,42 @@
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+// An Area with the `crc-trailer` property ends in a trailer that lets a
+// reader check the payload without knowing its size up front. The last
+// TRAILER_SIZE bytes of the Area are, little-endian:
+//
+//   u32  the payload size in bytes
+//   u32  the CRC-32 (ISO-HDLC, as used by zlib) of the payload
+//
+// The payload starts at the beginning of the Area; anything between it
+// and the trailer is padding and is not covered by the CRC.
+pub const TRAILER_SIZE: usize = 8;
+
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+pub fn trailer(payload: &[u8]) -> [u8; TRAILER_SIZE] {
+    let mut t = [0; TRAILER_SIZE];
+    t[..4].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+    t[4..].copy_from_slice(&CRC.checksum(payload).to_le_bytes());
+    t
+}
+
+// check_trailer: return the payload of an Area's bytes if its trailer is
+// intact, None otherwise.
+pub fn check_trailer(area: &[u8]) -> Option<&[u8]> {
+    let body = area.len().checked_sub(TRAILER_SIZE)?;
+    let word = |i: usize| u32::from_le_bytes(area[i..i + 4].try_into().unwrap());
+    let size = word(body) as usize;
+    let payload = area[..body].get(..size)?;
+    (CRC.checksum(payload) == word(body + 4)).then_some(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_crc() {
+        // The standard CRC-32 check value.
+        assert_eq!(trailer(b"123456789"), [9, 0, 0, 0, 0x26, 0x39, 0xf4, 0xcb]);
+    }
+}
@@ -0,0 +1,98 @@
+use crate::error::{LayoutError, Result};
+use log::{Level, Log, Metadata, Record};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// CountingLogger: counts the warnings logged, then hands every record on
+// to the logger that prints them. It counts warnings the inner logger's
+// filter hides too, so that --fail-on-warning still works with -q, as
+// long as log::max_level lets warnings through.
+pub struct CountingLogger<L> {
+    inner: L,
+    warnings: AtomicUsize,
+}
+
+impl<L> CountingLogger<L> {
+    pub const fn new(inner: L) -> Self {
+        CountingLogger {
+            inner,
+            warnings: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.warnings.load(Ordering::Relaxed)
+    }
+}
+
+impl<L: Log> Log for CountingLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= Level::Warn || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.level() == Level::Warn {
+            self.warnings.fetch_add(1, Ordering::Relaxed);
+        }
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+// check_warnings: with --fail-on-warning, any warning fails the run.
+pub fn check_warnings(warnings: usize, fail_on_warning: bool) -> Result<()> {
+    if fail_on_warning && warnings > 0 {
+        return Err(LayoutError::Warnings(warnings));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{compute_layout, LayoutOpts};
+    use crate::testutil::area;
+    use log::LevelFilter;
+
+    struct Silent;
+
+    impl Log for Silent {
+        fn enabled(&self, _: &Metadata) -> bool {
+            false
+        }
+        fn log(&self, _: &Record) {}
+        fn flush(&self) {}
+    }
+
+    static LOGGER: CountingLogger<Silent> = CountingLogger::new(Silent);
+
+    #[test]
+    fn fail_on_warning() {
+        log::set_logger(&LOGGER).unwrap();
+        log::set_max_level(LevelFilter::Warn);
+        // Other tests may log warnings at the same time, so only count up.
+        let before = LOGGER.warnings();
+        let opts = LayoutOpts {
+            allow_overlap: true,
+            ..Default::default()
+        };
+        let mut areas = [
+            area("area@0", Some(0), 0x100),
+            area("area@1", Some(0x80), 0x100),
+        ];
+        compute_layout(&mut areas, &opts).unwrap();
+        let warnings = LOGGER.warnings() - before;
+        assert!(warnings > 0);
+
+        assert!(matches!(
+            check_warnings(warnings, true),
+            Err(LayoutError::Warnings(_))
+        ));
+        check_warnings(warnings, false).unwrap();
+        check_warnings(0, true).unwrap();
+    }
+}
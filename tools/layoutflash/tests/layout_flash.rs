@@ -0,0 +1,64 @@
+// End-to-end tests: read the Areas of a fixture fdt from tests/data,
+// lay them out and write the image to a temporary file, as the command
+// line tool does. The .dts sources are next to the .dtb fixtures;
+// rebuild a fixture with `dtc -O dtb -o <name>.dtb <name>.dts`.
+use layoutflash_tool::{layout_flash, read_areas, AreaOpts, LayoutOpts, Result};
+use std::path::{Path, PathBuf};
+use std::{env, fs, io, process};
+
+// build: the image built from a fixture, or the error building it.
+fn build(fixture: &str) -> Result<Vec<u8>> {
+    let dtb = Path::new("tests/data").join(fixture).with_extension("dtb");
+    let out = out_path(fixture);
+    let mut areas = read_areas(&dtb, &AreaOpts::default())?;
+    let result = layout_flash(&out, &mut areas, &LayoutOpts::default(), &mut io::empty());
+    let image = fs::read(&out);
+    let _ = fs::remove_file(&out);
+    result?;
+    Ok(image?)
+}
+
+fn out_path(fixture: &str) -> PathBuf {
+    env::temp_dir().join(format!("layoutflash-{}-{}.bin", process::id(), fixture))
+}
+
+fn filled(len: usize, fill: u8) -> Vec<u8> {
+    vec![fill; len]
+}
+
+#[test]
+fn two_areas() {
+    let mut want = b"BOOTBLOB".to_vec();
+    want.extend(filled(8, 0xff));
+    want.extend(b"payload!");
+    want.extend(filled(8, 0xff));
+    assert_eq!(build("two-areas").unwrap(), want);
+}
+
+#[test]
+fn overlapping_areas() {
+    let err = build("overlap").unwrap_err();
+    assert!(err.to_string().contains("overlap"), "{}", err);
+}
+
+#[test]
+fn auto_offsets() {
+    // The payload is aligned up to 0x10, leaving a gap, and the NVRAM
+    // follows it.
+    let mut want = b"BOOTBLOB".to_vec();
+    want.extend(filled(8, 0));
+    want.extend(b"payload!");
+    want.extend(filled(8, 0));
+    assert_eq!(build("auto-offset").unwrap(), want);
+}
+
+#[test]
+fn oversized_file() {
+    let err = build("oversized").unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("File tests/data/bootblob.bin is too big to fit into the flash area"),
+        "{}",
+        err
+    );
+}